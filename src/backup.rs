@@ -0,0 +1,124 @@
+use crate::util::{self, color};
+use crate::{config, err, local, perms};
+
+use anyhow::Result;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+use walkdir::WalkDir;
+
+/// The time of this run of tittle, which names the directory its backups go in.
+static BACKUP_TIME: OnceLock<String> = OnceLock::new();
+
+/// A backup of a local file.
+///
+/// # Fields
+///
+/// * `time` - When the backup was made, formatted as `%Y-%m-%d_%H-%M-%S%.3f`.
+/// * `path` - The local path that was backed up.
+/// * `backup` - Where the backed up contents are stored.
+pub struct Backup {
+  pub time: String,
+  pub path: PathBuf,
+  pub backup: PathBuf,
+}
+
+/// Returns the path of the directory holding backups of local files.
+pub fn tittle_backup_dir() -> PathBuf {
+  config::tittle_config_dir().join(".backups")
+}
+
+/// Returns the name of the directory that backups made during this run go in.
+fn backup_time() -> &'static str {
+  BACKUP_TIME.get_or_init(|| {
+    chrono::Local::now()
+      .format("%Y-%m-%d_%H-%M-%S%.3f")
+      .to_string()
+  })
+}
+
+/// Returns where the backup of the local `path` made at `time` is stored.
+fn backup_path<P: AsRef<Path>>(time: &str, path: P) -> PathBuf {
+  let relative: PathBuf = path
+    .as_ref()
+    .components()
+    .filter(|component| matches!(component, Component::Normal(_)))
+    .collect();
+
+  tittle_backup_dir().join(time).join(relative)
+}
+
+/// Backs up the local file `path` before it is overwritten or deleted. Each file is
+/// backed up at most once per run, so the backup holds its contents from before this
+/// run. Backups keep the mode of `path`, and their directories are only readable by
+/// this user. Does nothing if `path` doesn't exist or is a symlink, or in dry-run mode.
+pub fn backup<P: AsRef<Path>>(path: P) -> Result<()> {
+  let path = path.as_ref();
+  if util::dry_run() || path.is_symlink() || !path.is_file() {
+    return Ok(());
+  }
+
+  let backup = backup_path(backup_time(), path);
+  if backup.exists() {
+    return Ok(());
+  }
+
+  util::create_private_dir(backup.parent().unwrap())?;
+  let mode = perms::mode(path)?.unwrap_or(0o600);
+  util::write_with_mode(backup, local::read(path)?, mode)?;
+
+  Ok(())
+}
+
+/// Returns all backups, oldest first.
+pub fn backups() -> Result<Vec<Backup>> {
+  let backup_dir = tittle_backup_dir();
+  if !backup_dir.is_dir() {
+    return Ok(Vec::new());
+  }
+
+  let mut backups = Vec::new();
+
+  let walk = WalkDir::new(&backup_dir).sort_by(|a, b| a.file_name().cmp(b.file_name()));
+
+  for backup in walk {
+    let backup = backup?;
+    if backup.file_type().is_dir() {
+      continue;
+    }
+
+    let relative = backup.path().strip_prefix(&backup_dir)?;
+    let mut components = relative.components();
+    let time = match components.next() {
+      Some(Component::Normal(time)) => time.to_string_lossy().to_string(),
+      _ => continue,
+    };
+
+    backups.push(Backup {
+      time,
+      path: Path::new("/").join(components.as_path()),
+      backup: backup.path().to_path_buf(),
+    });
+  }
+
+  Ok(backups)
+}
+
+/// Returns the backup of the local `path` made at `time`, or the most recent backup
+/// of `path` if `time` is `None`.
+pub fn find<P: AsRef<Path>>(path: P, time: Option<&str>) -> Result<Backup> {
+  let path = path.as_ref();
+
+  let backup = backups()?
+    .into_iter()
+    .rfind(|backup| backup.path == path && time.is_none_or(|time| backup.time == time));
+
+  match (backup, time) {
+    (Some(backup), _) => Ok(backup),
+    (None, Some(time)) => err::err(format!(
+      "No backup of {} at {}",
+      color::path(path),
+      color::emphasis(time)
+    )),
+    (None, None) => err::err(format!("No backups of {}", color::path(path))),
+  }
+}
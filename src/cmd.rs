@@ -1,7 +1,12 @@
 pub mod diff;
 pub mod edit;
+pub mod mv;
 pub mod remove;
 pub mod render;
+pub mod restore;
+pub mod retarget;
 pub mod sync;
+pub mod templatize;
 pub mod track;
 pub mod tree;
+pub mod undo;
@@ -1,16 +1,38 @@
 use crate::cmd::sync;
-use crate::config;
+use crate::config::{self, Mode};
+use crate::local;
 use crate::util::{self, color};
 
 use anyhow::Result;
+use std::path::Path;
 
-/// Prints any diffs between any remote and local dotfiles.
+/// Prints any diffs between any remote and local dotfiles. Entries in
+/// `Mode::Symlink` whose local path isn't a symlink to the remote path are reported
+/// as not linked.
 pub fn diff() -> Result<()> {
   let config = config::get_config()?;
   let tittle_config_dir = config::tittle_config_dir();
 
   for (remote, local) in config.dests().iter() {
-    let files = sync::remote_and_local_files(&remote, &local)?;
+    if config.mode(remote) == Mode::Symlink {
+      let remote_path = tittle_config_dir.join(remote);
+      if local::is_linked(&remote_path, local) {
+        continue;
+      }
+
+      util::info(format!(
+        "not linked {} -> {}",
+        color::path(local),
+        color::path(remote)
+      ));
+
+      // only a real local path has contents that can differ
+      if Path::new(local).read_link().is_ok() {
+        continue;
+      }
+    }
+
+    let files = sync::remote_and_local_files(remote, local)?;
 
     for (remote_file, local_file) in files.iter() {
       match util::diff(remote_file, local_file)? {
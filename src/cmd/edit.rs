@@ -2,6 +2,7 @@ use crate::util::{self, color};
 use crate::{config, err, git};
 
 use anyhow::Result;
+use serde::{de::DeserializeOwned, Serialize};
 use std::env;
 use std::fs::File;
 use std::process::Command;
@@ -13,6 +14,7 @@ use std::process::Command;
 /// * `mode` - Optional specifier of which portion of the config should be edited.
 ///            Valid values are:
 ///              - `"me"`: edit this machine's specific overrides.
+///              - `"vars"`: edit the template variables shared by every machine.
 pub fn edit(mode: Option<&str>) -> Result<()> {
   match mode {
     None => {
@@ -23,6 +25,9 @@ pub fn edit(mode: Option<&str>) -> Result<()> {
     Some("me") => {
       edit_machine()?;
     }
+    Some("vars") => {
+      edit_vars()?;
+    }
     Some(mode) => {
       return err::err(format!("Invalid edit mode {}", color::emphasis(mode)))
     }
@@ -35,32 +40,51 @@ pub fn edit(mode: Option<&str>) -> Result<()> {
 
 /// Edit this machine's specific overrides.
 fn edit_machine() -> Result<()> {
-  use std::io::prelude::*;
-
   let machine_id = util::machine_id()?;
-  let mut temp_override_path = env::temp_dir();
-  temp_override_path.push(format!("overrides-{}", machine_id));
-
   let mut config = config::get_config()?;
-  let old_overrides = serde_json::to_string_pretty(&config.my_overrides())?;
-  let mut temp_override_file = File::create(&temp_override_path)?;
-  temp_override_file.write_all(&old_overrides.as_bytes())?;
-  temp_override_file.flush()?;
 
-  Command::new(editor()?).arg(&temp_override_path).status()?;
+  let overrides =
+    edit_json(&format!("overrides-{}", machine_id), &config.my_overrides())?;
+  config.set_my_overrides(overrides)?;
 
-  let mut new_overrides = String::new();
-  File::open(temp_override_path)?.read_to_string(&mut new_overrides)?;
+  config::write_config(&config)
+}
+
+/// Edit the template variables shared by every machine.
+fn edit_vars() -> Result<()> {
+  let machine_id = util::machine_id()?;
+  let mut config = config::get_config()?;
 
-  config.set_my_overrides(serde_json::from_str(&new_overrides)?)?;
+  let vars = edit_json(&format!("vars-{}", machine_id), &config.shared_vars())?;
+  config.set_shared_vars(vars);
 
   config::write_config(&config)
 }
 
+/// Opens `value` as JSON in `$EDITOR`, in the temporary file `temp_name`, and returns
+/// the edited value.
+fn edit_json<T: Serialize + DeserializeOwned>(temp_name: &str, value: &T) -> Result<T> {
+  use std::io::prelude::*;
+
+  let mut temp_path = env::temp_dir();
+  temp_path.push(temp_name);
+
+  let mut temp_file = File::create(&temp_path)?;
+  temp_file.write_all(serde_json::to_string_pretty(value)?.as_bytes())?;
+  temp_file.flush()?;
+
+  Command::new(editor()?).arg(&temp_path).status()?;
+
+  let mut edited = String::new();
+  File::open(temp_path)?.read_to_string(&mut edited)?;
+
+  Ok(serde_json::from_str(&edited)?)
+}
+
 /// Returns this machine's `$EDITOR`.
 pub fn editor() -> Result<String> {
   match env::var("EDITOR") {
     Ok(editor) => Ok(editor),
-    Err(_) => return err::err("Please set an $EDITOR to edit the tittle config."),
+    Err(_) => err::err("Please set an $EDITOR to edit the tittle config."),
   }
 }
@@ -0,0 +1,108 @@
+use crate::cmd::sync;
+use crate::config::{self, Mode};
+use crate::util::{self, color};
+use crate::{err, git, local, perms, state};
+
+use anyhow::Result;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Renames the remote name `from` to `to`. The name `from` is either the name of an
+/// entry or a directory containing entries, like `tmux` for `tmux/.tmux.conf`, and
+/// every entry under it is renamed.
+///
+/// The remote files are moved with `git mv`, and the entries are renamed in `dest`,
+/// `templates` and every machine's overrides. Local symlinks of entries in
+/// `Mode::Symlink` are pointed at the new remote path.
+pub fn mv(from: &str, to: &str) -> Result<()> {
+  let (from, to) = (from.trim_end_matches('/'), to.trim_end_matches('/'));
+  let tittle_config_dir = config::tittle_config_dir();
+
+  let mut config = config::get_config()?;
+  let entries = config.entries(from);
+  if entries.is_empty() {
+    return err::err(format!(
+      "The name '{}' is not being tracked",
+      color::emphasis(from)
+    ));
+  }
+
+  let inside_entry = config
+    .entry_of(to)
+    .filter(|entry| !Path::new(entry).starts_with(from));
+  if !config.entries(to).is_empty()
+    || inside_entry.is_some()
+    || util::exists(tittle_config_dir.join(to))
+  {
+    return err::err(format!(
+      "The name '{}' is already being used",
+      color::emphasis(to)
+    ));
+  }
+
+  if Path::new(to).starts_with(from) {
+    return err::err(format!(
+      "Can't move '{}' into itself",
+      color::emphasis(from)
+    ));
+  }
+
+  let mut state = state::get_state()?;
+  let mut permissions = perms::get_permissions()?;
+  let mut relink = Vec::new();
+
+  for entry in &entries {
+    let renamed_entry = renamed(entry, from, to)?.to_string_lossy().to_string();
+
+    let local = config.dest(entry);
+    for (remote_file, _) in sync::remote_and_local_files(entry, &local)? {
+      let renamed_file = renamed(
+        &remote_file,
+        tittle_config_dir.join(from),
+        tittle_config_dir.join(to),
+      )?;
+
+      state.rename(&remote_file, &renamed_file);
+      permissions.rename(&remote_file, &renamed_file);
+    }
+
+    let remote = tittle_config_dir.join(entry);
+    if config.mode(entry) == Mode::Symlink && local::is_linked(&remote, &local) {
+      relink.push((tittle_config_dir.join(&renamed_entry), local));
+    }
+
+    config.rename(entry, &renamed_entry);
+  }
+
+  if !util::dry_run() {
+    git::mv(from, to)?;
+    util::remove_empty_parents(tittle_config_dir.join(from), &tittle_config_dir)?;
+  }
+
+  util::info(format!("move {} -> {}", color::path(from), color::path(to)));
+
+  for (remote, local) in relink {
+    sync::link(remote, local)?;
+  }
+
+  config::write_config(&config)?;
+  perms::write_permissions(&permissions)?;
+
+  git::commit(&env::args().collect::<Vec<String>>()[1..].join(" "))?;
+
+  state::write_state(&state)
+}
+
+/// Returns `path` under `to` instead of `from`.
+fn renamed<P: AsRef<Path>, Q: AsRef<Path>, R: AsRef<Path>>(
+  path: P,
+  from: Q,
+  to: R,
+) -> Result<PathBuf> {
+  let relative = path.as_ref().strip_prefix(from)?;
+  if relative.as_os_str().is_empty() {
+    Ok(to.as_ref().to_path_buf())
+  } else {
+    Ok(to.as_ref().join(relative))
+  }
+}
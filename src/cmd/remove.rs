@@ -1,25 +1,98 @@
+use crate::cmd::sync;
+use crate::config::{self, Config, Mode};
 use crate::util::{self, color};
-use crate::{cmd::edit, config, err};
+use crate::{cmd::edit, err, git, local, perms, state};
 
 use anyhow::Result;
 use std::collections::HashMap;
 use std::env;
 use std::fs::File;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
-pub fn remove() -> Result<()> {
+/// Removes the entries tracked under `names`. If `names` is empty, the entries to
+/// remove are chosen by deleting them from the list of entries in `$EDITOR`.
+///
+/// The entries are removed from `dest` and `templates`, including every machine's
+/// overrides, and their files are deleted from the tittle repository. Local files are
+/// left in place, except that symlinked entries have their symlink replaced with a
+/// copy of their files. If `delete_local` is set, the local files, and the rendered
+/// files of templates, are deleted instead.
+pub fn remove(names: Vec<&str>, delete_local: bool) -> Result<()> {
+  let mut config = config::get_config()?;
+
+  let names = if names.is_empty() {
+    choose_in_editor(&config)?
+  } else {
+    let mut entries = Vec::new();
+    for name in names {
+      let found = config.entries(name);
+      if found.is_empty() {
+        return err::err(format!(
+          "The name '{}' is not being tracked",
+          color::emphasis(name)
+        ));
+      }
+
+      entries.extend(found);
+    }
+    entries.sort();
+    entries.dedup();
+    entries
+  };
+
+  let tittle_config_dir = config::tittle_config_dir();
+  let templates = config.templates();
+  let mut state = state::get_state()?;
+  let mut permissions = perms::get_permissions()?;
+
+  for name in &names {
+    let remote = tittle_config_dir.join(name);
+    let local = PathBuf::from(config.dest(name));
+    let files = sync::remote_and_local_files(name, &local)?;
+
+    if delete_local {
+      remove_local(&local, &files)?;
+
+      if let Some(render_to) = templates.get(name) {
+        remove_local(Path::new(render_to), &[])?;
+      }
+    } else if config.mode(name) == Mode::Symlink && local::is_linked(&remote, &local) {
+      unlink(&remote, &local, &files)?;
+    }
+
+    for (remote_file, _) in &files {
+      state.forget(remote_file);
+      permissions.forget(remote_file);
+    }
+
+    remove_remote(&remote)?;
+    config.untrack(name);
+
+    util::info(format!("remove {}", color::path(name)));
+  }
+
+  config::write_config(&config)?;
+  perms::write_permissions(&permissions)?;
+
+  git::commit(&env::args().collect::<Vec<String>>()[1..].join(" "))?;
+
+  state::write_state(&state)
+}
+
+/// Lets the user choose entries to remove by deleting them in `$EDITOR`, and returns
+/// the names of the deleted entries.
+fn choose_in_editor(config: &Config) -> Result<Vec<String>> {
   use std::io::prelude::*;
 
   let machine_id = util::machine_id()?;
   let mut temp_dests_path = env::temp_dir();
   temp_dests_path.push(format!("remove-{}", machine_id));
 
-  let config = config::get_config()?;
   let old_dests = config.dests();
 
   let mut temp_dests_file = File::create(&temp_dests_path)?;
-  temp_dests_file
-    .write_all(&serde_json::to_string_pretty(&config.dests())?.as_bytes())?;
+  temp_dests_file.write_all(serde_json::to_string_pretty(&old_dests)?.as_bytes())?;
   temp_dests_file.flush()?;
 
   Command::new(edit::editor()?)
@@ -40,16 +113,68 @@ pub fn remove() -> Result<()> {
     }
   }
 
-  let mut removed_keys = Vec::new();
+  Ok(
+    old_dests
+      .into_keys()
+      .filter(|key| !remaining_dests.contains_key(key))
+      .collect(),
+  )
+}
+
+/// Deletes the local path `local` of a removed entry, whose files are `files`. Does
+/// nothing if it doesn't exist.
+fn remove_local(local: &Path, files: &[(PathBuf, PathBuf)]) -> Result<()> {
+  if !util::exists(local) {
+    return Ok(());
+  }
+
+  if local.is_symlink() || !local.is_dir() {
+    local::remove(local)?;
+  } else {
+    for (_, local_file) in files {
+      if util::exists(local_file) {
+        local::remove(local_file)?;
+      }
+    }
 
-  for (key, _) in old_dests.iter() {
-    if !remaining_dests.contains_key(key) {
-      removed_keys.push(key);
-      util::info(format!("Removing entry '{}'", color::emphasis(key)));
+    // ignored files are left alone, and so are the directories of system entries
+    if !util::dry_run() && !local::is_system(local) {
+      util::remove_empty_dirs(local)?;
     }
   }
 
-  // remove these keys from default and override dest/templates.
+  util::info(format!("delete {}", color::path(local)));
 
   Ok(())
 }
+
+/// Replaces the symlink `local` to the `remote` path of a removed entry with a copy of
+/// its `files`, so that they outlive the entry.
+fn unlink(remote: &Path, local: &Path, files: &[(PathBuf, PathBuf)]) -> Result<()> {
+  local::unlink(local)?;
+
+  for (remote_file, local_file) in files {
+    if util::exists(remote_file) {
+      local::copy(remote_file, local_file)?;
+    }
+  }
+
+  util::info(format!(
+    "unlink {}, replacing it with a copy of {}",
+    color::path(local),
+    color::path(remote)
+  ));
+
+  Ok(())
+}
+
+/// Deletes the `remote` path of a removed entry from the tittle repository, along with
+/// any directories it leaves empty.
+fn remove_remote(remote: &Path) -> Result<()> {
+  if util::dry_run() || !util::exists(remote) {
+    return Ok(());
+  }
+
+  util::remove_all(remote)?;
+  util::remove_empty_parents(remote, config::tittle_config_dir())
+}
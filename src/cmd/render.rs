@@ -1,47 +1,198 @@
-use crate::util::color;
-use crate::{config, err};
+use crate::config::{self, Config, Var};
+use crate::template::Template;
+use crate::util::{self, color};
+use crate::{err, git, local, perms, secrets};
 
 use anyhow::Result;
-use regex::Regex;
-use std::collections::HashMap;
-use std::fs::File;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::{self, File};
 use std::path::Path;
+use std::process::{Command, Stdio};
 
-/// Render a template to its location given the replacement variables.
+/// Render a template to its location given the replacement variables. The rendered
+/// file gets the permission bits `mode`, or those of the template if `mode` is `None`,
+/// and the `owner` and group recorded for the template if it is a system entry.
+///
+/// Variables whose value comes from a command or a file are only evaluated if the
+/// template uses them, and their values are kept in `cache` for the next templates.
+/// Using one of the `untrusted` variables is an error, see `untrusted_vars`.
 fn render_template<P: AsRef<Path>, Q: AsRef<Path>>(
   template: P,
   render_to: Q,
-  vars: &HashMap<String, String>,
+  vars: &HashMap<String, Var>,
+  untrusted: &HashSet<String>,
+  cache: &mut HashMap<String, Var>,
+  mode: Option<u32>,
+  owner: Option<(String, String)>,
 ) -> Result<()> {
   use std::io::prelude::*;
 
-  let mut contents = String::new();
-  File::open(&template)?.read_to_string(&mut contents)?;
-  for (var, value) in vars.iter() {
-    contents = contents.replace(&format!("{{{{{}}}}}", var.trim()), value);
-  }
-  let re = Regex::new(r"\{\{([^}]*)\}\}").unwrap();
-  if let Some(var) = re.captures(&contents) {
-    return err::err(format!(
-      "In template {}: this machine has no value for variable {}",
-      color::path(template),
-      color::emphasis(var[1].to_owned())
-    ));
+  let mut source = String::new();
+  File::open(&template)?.read_to_string(&mut source)?;
+
+  let contents = Template::parse(&source).and_then(|parsed| {
+    let mut vars = vars.clone();
+    for var in parsed.vars() {
+      if let Some(value @ (Var::Cmd { .. } | Var::File { .. })) = vars.get(var) {
+        if untrusted.contains(var) {
+          return err::err(format!(
+            "variable {} runs a command or reads a file, which is only allowed in this \
+             machine's overrides, see {}",
+            color::emphasis(var),
+            color::emphasis("tittle edit me")
+          ));
+        }
+        if !cache.contains_key(var) {
+          cache.insert(var.to_owned(), evaluate(var, value)?);
+        }
+        vars.insert(var.to_owned(), cache[var].clone());
+      }
+    }
+
+    parsed.render(&vars)
+  });
+
+  let contents = match contents {
+    Ok(contents) => contents,
+    Err(e) => return err::err(format!("In template {}, {}", color::path(template), e)),
+  };
+
+  util::info(format!(
+    "render {} -> {}",
+    color::path(&template),
+    color::path(&render_to)
+  ));
+
+  match mode.or(perms::mode(&template)?) {
+    Some(mode) => local::write_with_mode(&render_to, contents, mode)?,
+    None => local::write(&render_to, contents)?,
   }
 
-  write!(File::create(render_to)?, "{}", contents)?;
+  if let Some((owner, group)) = owner {
+    if perms::owner(&render_to)? != Some((owner.clone(), group.clone())) {
+      local::set_owner(&render_to, &owner, &group)?;
+    }
+  }
 
   Ok(())
 }
 
-/// Render all local templates to their location.
+/// Returns the value of the variable `var`, by running its command or reading its
+/// file. A single trailing newline is removed.
+fn evaluate(var: &str, value: &Var) -> Result<Var> {
+  let output = match value {
+    Var::Cmd { cmd } => {
+      let output = Command::new("sh")
+        .args(["-c", cmd])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+      if !output.status.success() {
+        return err::err(format!(
+          "variable {}: command {} failed with {}",
+          color::emphasis(var),
+          color::emphasis(cmd),
+          output.status
+        ));
+      }
+
+      String::from_utf8(output.stdout)
+    }
+    Var::File { file } => {
+      let path = util::expand(file)?.remove(0);
+      match fs::read(&path) {
+        Ok(contents) => String::from_utf8(contents),
+        Err(e) => {
+          return err::err(format!(
+            "variable {}: can't read {}: {}",
+            color::emphasis(var),
+            color::path(path),
+            e
+          ))
+        }
+      }
+    }
+    value => return Ok(value.clone()),
+  };
+
+  match output {
+    Ok(output) => {
+      let output = output.strip_suffix('\n').unwrap_or(&output);
+      Ok(Var::String(output.to_owned()))
+    }
+    Err(_) => err::err(format!(
+      "variable {}: its value isn't UTF-8 text",
+      color::emphasis(var)
+    )),
+  }
+}
+
+/// Returns the variables in `vars` that run a command or read a file but don't come
+/// from this machine's overrides. The shared variables can be changed by anyone who
+/// can push to the repository, so they aren't trusted to run commands.
+fn untrusted_vars(config: &Config, vars: &HashMap<String, Var>) -> HashSet<String> {
+  let machine_vars = config.machine_vars();
+  vars
+    .iter()
+    .filter(|(var, value)| {
+      matches!(value, Var::Cmd { .. } | Var::File { .. })
+        && machine_vars.get(*var) != Some(value)
+    })
+    .map(|(var, _)| var.clone())
+    .collect()
+}
+
+/// Returns the built-in template variables, which describe this machine. These are
+/// `tittle.hostname`, `tittle.user`, `tittle.home`, `tittle.os`, `tittle.arch` and
+/// `tittle.machine_id`, along with `env.NAME` for each environment variable `NAME`.
+fn builtin_vars() -> Result<HashMap<String, Var>> {
+  let mut vars: HashMap<String, Var> = env::vars()
+    .map(|(name, value)| (format!("env.{}", name), Var::String(value)))
+    .collect();
+
+  for (var, value) in [
+    ("hostname", util::hostname()?),
+    ("user", util::username()?),
+    ("home", env::var("HOME")?),
+    ("os", env::consts::OS.to_owned()),
+    ("arch", env::consts::ARCH.to_owned()),
+    ("machine_id", util::machine_id()?),
+  ] {
+    vars.insert(format!("tittle.{}", var), Var::String(value));
+  }
+
+  Ok(vars)
+}
+
+/// Render all local templates to their location. The variables of this machine's
+/// overrides are used, along with those extracted from secrets in `.secrets.json`, and
+/// the built-in variables, which can't be overridden.
 pub fn render() -> Result<()> {
   let config = config::get_config()?;
-  let vars = config.vars();
+  let mut vars = config.vars();
+  vars.extend(
+    secrets::get_secrets()?
+      .vars
+      .into_iter()
+      .map(|(var, value)| (var, Var::String(value))),
+  );
+  vars.extend(builtin_vars()?);
+  let untrusted = untrusted_vars(&config, &vars);
+  let mut cache = HashMap::new();
+  let permissions = perms::get_permissions()?;
 
   for (remote_name, render_to) in config.templates().iter() {
-    render_template(config.dest(remote_name), render_to, &vars)?;
+    render_template(
+      config.dest(remote_name),
+      render_to,
+      &vars,
+      &untrusted,
+      &mut cache,
+      config.template_mode(remote_name)?,
+      permissions.owner(config::tittle_config_dir().join(remote_name)),
+    )?;
   }
 
-  Ok(())
+  git::commit(&env::args().collect::<Vec<String>>()[1..].join(" "))
 }
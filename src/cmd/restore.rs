@@ -0,0 +1,48 @@
+use crate::util::{self, color};
+use crate::{backup, git, local};
+
+use anyhow::Result;
+use std::env;
+use std::path::Path;
+
+/// Restores the local file `path` from its backup made at `time`, or from its most
+/// recent backup if `time` is `None`. The contents being replaced are backed up as
+/// well, so a restore can itself be restored. If `path` is `None`, then all backups
+/// are listed instead.
+pub fn restore(path: Option<&str>, time: Option<&str>) -> Result<()> {
+  let path = match path {
+    Some(path) => env::current_dir()?.join(path),
+    None => return list(),
+  };
+
+  let backup = backup::find(&path, time)?;
+
+  local::copy(&backup.backup, &path)?;
+
+  util::info(format!(
+    "restore {} from {}",
+    color::path(&path),
+    color::emphasis(&backup.time)
+  ));
+
+  git::commit(&env::args().collect::<Vec<String>>()[1..].join(" "))
+}
+
+/// Lists all backups, grouped by the time they were made.
+fn list() -> Result<()> {
+  let backups = backup::backups()?;
+  if backups.is_empty() {
+    util::info("No backups");
+  }
+
+  let mut last_time = None;
+  for backup in backups.iter() {
+    if last_time != Some(&backup.time) {
+      util::info(color::emphasis(&backup.time));
+      last_time = Some(&backup.time);
+    }
+    println!("  {}", color::path(Path::new(&backup.path)));
+  }
+
+  Ok(())
+}
@@ -0,0 +1,150 @@
+use crate::cmd::sync;
+use crate::config::{self, Mode};
+use crate::util::{self, color};
+use crate::{err, git, local, state};
+
+use anyhow::Result;
+use std::env;
+use std::path::{self, Path, PathBuf};
+
+/// Changes the local path of the entry `name` to `path`, either for every machine, or
+/// only for this machine if `machine` is set. Like `track`, a directory entry must
+/// point to a directory, and a file entry to a file.
+///
+/// If `move_local` is set, the local files are moved from the old path to `path`.
+/// Otherwise they are left alone, and this machine forgets that they were synced, so
+/// the next `sync` copies the remote files to `path` instead of deleting them.
+pub fn retarget<P: AsRef<Path>>(
+  name: &str,
+  path: P,
+  machine: bool,
+  move_local: bool,
+) -> Result<()> {
+  let path = path.as_ref();
+
+  let mut config = config::get_config()?;
+  let name = match config.entries(name).as_slice() {
+    [] => {
+      return err::err(format!(
+        "The name '{}' is not being tracked",
+        color::emphasis(name)
+      ))
+    }
+    [entry] => entry.clone(),
+    entries => {
+      return err::err(format!(
+        "The name '{}' has several entries, choose one of: {}",
+        color::emphasis(name),
+        entries.join(", ")
+      ))
+    }
+  };
+  let name = name.as_str();
+
+  let path = if path.exists() {
+    path.canonicalize()?
+  } else {
+    path::absolute(path)?
+  };
+
+  let remote = config::tittle_config_dir().join(name);
+  let old = PathBuf::from(config.dest(name));
+
+  let kind = |is_dir: bool| if is_dir { "directory" } else { "file" };
+  if util::exists(&path) {
+    if move_local {
+      return err::err(format!(
+        "Can't move {} to {}, it already exists",
+        color::path(&old),
+        color::path(&path)
+      ));
+    }
+
+    if path.is_dir() != remote.is_dir() {
+      return err::err(format!(
+        "'{}' is a {}, but '{}' is a {}",
+        path.display(),
+        kind(path.is_dir()),
+        name,
+        kind(remote.is_dir())
+      ));
+    }
+  }
+
+  if move_local {
+    move_files(name, &remote, &old, &path, config.mode(name))?;
+  }
+
+  config.retarget(name, path.to_string_lossy(), machine)?;
+
+  util::info(format!(
+    "retarget {} to {}",
+    color::path(name),
+    color::path(&path)
+  ));
+
+  let new = PathBuf::from(config.dest(name));
+  if new != path {
+    util::warn(format!(
+      "this machine still uses {}, from its overrides",
+      color::path(&new)
+    ));
+  }
+
+  let mut state = state::get_state()?;
+  if new != old && !move_local {
+    for (remote_file, _) in sync::remote_and_local_files(name, &old)? {
+      state.forget(remote_file);
+    }
+  }
+
+  config::write_config(&config)?;
+
+  git::commit(&env::args().collect::<Vec<String>>()[1..].join(" "))?;
+
+  state::write_state(&state)
+}
+
+/// Moves the local files of the entry `name`, whose remote path is `remote`, from
+/// `from` to `to`. Entries in `Mode::Symlink` are relinked instead.
+fn move_files(
+  name: &str,
+  remote: &Path,
+  from: &Path,
+  to: &Path,
+  mode: Mode,
+) -> Result<()> {
+  if !util::exists(from) {
+    util::warn(format!(
+      "{} doesn't exist, not moving it",
+      color::path(from)
+    ));
+    return Ok(());
+  }
+
+  if mode == Mode::Symlink && local::is_linked(remote, from) {
+    local::unlink(from)?;
+    return sync::link(remote, to);
+  }
+
+  if from.is_dir() {
+    for (_, local_file) in sync::remote_and_local_files(name, from)? {
+      if util::exists(&local_file) {
+        local::copy(&local_file, to.join(local_file.strip_prefix(from)?))?;
+        local::remove(&local_file)?;
+      }
+    }
+
+    // ignored files are left alone, and so are the directories of system entries
+    if !util::dry_run() && !local::is_system(from) {
+      util::remove_empty_dirs(from)?;
+    }
+  } else {
+    local::copy(from, to)?;
+    local::remove(from)?;
+  }
+
+  util::info(format!("move {} -> {}", color::path(from), color::path(to)));
+
+  Ok(())
+}
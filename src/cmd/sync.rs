@@ -1,114 +1,719 @@
+use crate::config::{self, Config, Mode};
+use crate::ignore::Ignore;
+use crate::perms::{self, Permissions};
+use crate::secrets::{self, Secret};
+use crate::state::{self, FileState, State};
 use crate::util::{self, color};
-use crate::{config, git};
+use crate::{cmd::edit, git, local};
 
 use anyhow::Result;
 use std::cmp::max;
+use std::collections::BTreeSet;
 use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::time::SystemTime;
 use std::{env, fs};
 use walkdir::WalkDir;
 
 /// Represents which direction to sync dotfiles.
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone)]
 enum SyncDirection {
   FromRemote,
   ToRemote,
   NoDiff,
+  Merge,
+}
+
+impl SyncDirection {
+  /// Returns which side is copied onto the other in this direction.
+  fn source(&self) -> &'static str {
+    match self {
+      SyncDirection::FromRemote => "remote",
+      SyncDirection::ToRemote => "local",
+      SyncDirection::NoDiff | SyncDirection::Merge => "neither",
+    }
+  }
+}
+
+/// The outcome of syncing a single pair of files.
+enum SyncResult {
+  Synced,
+  Removed,
+  Conflict,
+  Skipped,
 }
 
 /// Synchronizes the remote and local dotfiles.
 ///
-/// If two files are the same, then no copying occurs. Otherwise, the newer file
-/// remains and is copied onto the other. The timestamp of a file depends on whether
-/// it is a local file or a remote file. Remote files have their timestamps determined
-/// by the time of the most recent commit which modifies them. Local files' timestamps
-/// are determined by the filesystem.
-pub fn sync() -> Result<()> {
-  use SyncDirection::*;
+/// The direction to sync is decided for each pair of files. If two files are the
+/// same, then no copying occurs. If this machine has synced a file before, the hash of
+/// its contents at the last sync, kept in the machine-local `State`, determines which
+/// side changed. If only one side changed, it is copied onto the other. If both sides
+/// changed, a three-way merge is performed, using the commit the file was last synced
+/// from as the base. When the merge has conflicts, the conflict markers are written
+/// to the local file, and the remote file is left alone until the conflicts are
+/// resolved and `sync` is run again.
+///
+/// Files that have never been synced on this machine fall back to comparing
+/// timestamps, where the newer file remains and is copied onto the other. The
+/// timestamp of a file depends on whether it is a local file or a remote file. Remote
+/// files have their timestamps determined by the time of the most recent commit which
+/// modifies them. Local files' timestamps are determined by the filesystem.
+///
+/// If `whole_entry` is set, then a single direction is chosen for every file in a
+/// tracked directory, by comparing the newest remote and local timestamps across all
+/// of its files.
+///
+/// If `interactive` is set, then the diff of every pair of files that differ is shown,
+/// and the user chooses to keep the local file, keep the remote file, merge them in
+/// `$EDITOR`, or skip them. The direction that would have been chosen is the default.
+///
+/// Entries in `Mode::Symlink` are skipped if their local path is already a symlink to
+/// the remote path. Otherwise, any local files are synced as usual, and then the local
+/// path is replaced with a symlink, unless some of its files were left unresolved.
+pub fn sync(whole_entry: bool, interactive: bool) -> Result<()> {
+  let mut state = state::get_state()?;
+  let mut permissions = perms::get_permissions()?;
+  let mut synced = Vec::new();
+  let mut removed = Vec::new();
+  let mut conflicts = Vec::new();
 
-  for (remote, local) in config::get_config()?.dests().iter() {
-    let files = remote_and_local_files(&remote, &local)?;
+  let config = config::get_config()?;
+  let tittle_config_dir = config::tittle_config_dir();
 
-    let direction = sync_direction(&files);
-    if direction == NoDiff {
+  if !secrets::allow_secrets() {
+    let found = find_secrets(&config, &state, whole_entry, interactive)?;
+    if !found.is_empty() {
+      secrets::report(&found);
+      return secrets::refuse("commit");
+    }
+  }
+
+  for (remote, local) in config.dests().iter() {
+    let symlink = config.mode(remote) == Mode::Symlink;
+    let remote_path = tittle_config_dir.join(remote);
+    let local_path = Path::new(local);
+
+    if symlink && local::is_linked(&remote_path, local_path) {
       continue;
     }
 
-    for (remote_file, local_file) in files.iter() {
-      let arrow_str = if direction == FromRemote {
-        if !local_file
-          .parent()
-          .expect(&format!("Local path has no parent {:?}", local_file))
-          .is_dir()
-        {
-          fs::create_dir_all(local_file.parent().unwrap())?
+    // a missing local path or a stale symlink has nothing to sync
+    if symlink && (local_path.read_link().is_ok() || !local_path.exists()) {
+      link(&remote_path, local_path)?;
+      continue;
+    }
+
+    let files = remote_and_local_files(remote, local)?;
+    let mut unresolved = false;
+    let entry_direction = if whole_entry {
+      Some(sync_direction(&files, &state)?)
+    } else {
+      None
+    };
+
+    for (remote_file, local_file) in files {
+      let file_state = state.get(&remote_file);
+
+      if let Some(file_state) = file_state {
+        if file_state.conflict && has_conflict_markers(&local_file)? {
+          util::warn(format!(
+            "skipping {}, it has unresolved conflicts",
+            color::path(&local_file)
+          ));
+          unresolved = true;
+          continue;
         }
-        fs::copy(remote_file, local_file)?;
-        "->"
+      }
+
+      let (direction, reason) =
+        file_direction(&remote_file, &local_file, &entry_direction, &state)?;
+
+      let result = sync_files(
+        &remote_file,
+        &local_file,
+        local_path,
+        direction,
+        &reason,
+        file_state,
+        interactive,
+      )?;
+
+      match result {
+        SyncResult::Synced => {
+          sync_mode(&remote_file, &local_file, file_state, &mut permissions)?;
+          sync_owner(&remote_file, &local_file, &permissions)?;
+          synced.push(remote_file)
+        }
+        SyncResult::Removed => {
+          permissions.forget(&remote_file);
+          removed.push(remote_file)
+        }
+        SyncResult::Conflict => {
+          unresolved = true;
+          conflicts.push(remote_file)
+        }
+        SyncResult::Skipped => unresolved = true,
+      }
+    }
+
+    if symlink {
+      if unresolved {
+        util::warn(format!(
+          "not linking {}, some of its files are unresolved",
+          color::path(local_path)
+        ));
       } else {
-        fs::copy(local_file, remote_file)?;
-        "<-"
+        link(&remote_path, local_path)?;
+      }
+    }
+  }
+
+  perms::write_permissions(&permissions)?;
+
+  git::commit(&env::args().collect::<Vec<String>>()[1..].join(" "))?;
+
+  let head = git::head()?;
+  for remote_file in synced {
+    state.synced(remote_file, &head)?;
+  }
+  for remote_file in conflicts {
+    state.conflicted(remote_file, &head)?;
+  }
+  for remote_file in removed {
+    state.forget(remote_file);
+  }
+
+  state::write_state(&state)
+}
+
+/// Returns the secrets in the local files that syncing would copy or merge into the
+/// remote, along with the file they're in, so that they're refused before anything
+/// is written. In `interactive` mode any local file that differs could be kept, so
+/// all of them are checked.
+fn find_secrets(
+  config: &Config,
+  state: &State,
+  whole_entry: bool,
+  interactive: bool,
+) -> Result<Vec<(PathBuf, Vec<Secret>)>> {
+  use SyncDirection::*;
+
+  let mut found = Vec::new();
+
+  for (remote, local) in config.dests().iter() {
+    let local_path = Path::new(local);
+    if config.mode(remote) == Mode::Symlink
+      && (local_path.read_link().is_ok() || !local_path.exists())
+    {
+      continue;
+    }
+
+    let files = remote_and_local_files(remote, local)?;
+    let entry_direction = if whole_entry {
+      Some(sync_direction(&files, state)?)
+    } else {
+      None
+    };
+
+    for (remote_file, local_file) in files {
+      let (direction, _) =
+        file_direction(&remote_file, &local_file, &entry_direction, state)?;
+      let kept = match direction {
+        ToRemote | Merge => true,
+        FromRemote => interactive,
+        NoDiff => false,
       };
 
+      if kept {
+        let secrets = secrets::scan_file(&local_file)?;
+        if !secrets.is_empty() {
+          found.push((local_file, secrets));
+        }
+      }
+    }
+  }
+
+  Ok(found)
+}
+
+/// Replaces the local path of a symlinked entry with a symlink to its `remote` path.
+pub fn link<P: AsRef<Path>, Q: AsRef<Path>>(remote: P, local: Q) -> Result<()> {
+  util::info(format!(
+    "link {} -> {}",
+    color::path(local.as_ref()),
+    color::path(remote.as_ref())
+  ));
+
+  local::symlink(remote, local)
+}
+
+/// Syncs the permission bits of `remote_file` and `local_file`, after their contents
+/// were synced. The mode recorded in `permissions` is applied to the local file, unless
+/// the local file's mode changed since it was last synced, in which case the local
+/// mode is recorded instead.
+fn sync_mode(
+  remote_file: &Path,
+  local_file: &Path,
+  file_state: Option<&FileState>,
+  permissions: &mut Permissions,
+) -> Result<()> {
+  if local_file.is_symlink() {
+    return Ok(());
+  }
+
+  let local_mode = match perms::mode(local_file)? {
+    Some(local_mode) => local_mode,
+    None => return Ok(()),
+  };
+
+  let recorded = permissions.mode(remote_file)?;
+  if recorded == Some(local_mode) {
+    return Ok(());
+  }
+
+  let local_unchanged = match file_state {
+    Some(file_state) => file_state.mode == Some(local_mode),
+    None => true,
+  };
+
+  match recorded {
+    Some(recorded) if local_unchanged => {
+      local::set_mode(local_file, recorded)?;
+      if !util::dry_run() && remote_file.is_file() {
+        perms::set_mode(remote_file, recorded)?;
+      }
+
+      util::info(format!(
+        "mode {} {} (remote changed)",
+        color::path(local_file),
+        perms::format_mode(recorded)
+      ));
+    }
+    _ => {
+      permissions.record(remote_file, local_file)?;
+
       util::info(format!(
-        "sync {} {} {}",
+        "mode {} {} (local changed)",
         color::path(remote_file),
-        arrow_str,
-        color::path(local_file)
+        perms::format_mode(local_mode)
       ));
     }
   }
 
-  git::commit(&env::args().collect::<Vec<String>>()[1..].join(" "))?;
+  Ok(())
+}
+
+/// Restores the owner and group recorded in `permissions` for `remote_file` on its
+/// local file, if it belongs to a system entry, after their contents were synced.
+fn sync_owner(
+  remote_file: &Path,
+  local_file: &Path,
+  permissions: &Permissions,
+) -> Result<()> {
+  if local_file.is_symlink() || !local::is_system(local_file) {
+    return Ok(());
+  }
+
+  let (owner, group) = match (permissions.owner(remote_file), perms::owner(local_file)?) {
+    (Some(recorded), Some(local)) if recorded != local => recorded,
+    _ => return Ok(()),
+  };
+
+  local::set_owner(local_file, &owner, &group)?;
+
+  util::info(format!(
+    "owner {} {}:{}",
+    color::path(local_file),
+    owner,
+    group
+  ));
 
   Ok(())
 }
 
-/// Returns the pairs of corresponding files under a tracked file or directory.
+/// Syncs a single pair of files in `direction`, which was chosen for `reason`. If
+/// `interactive` is set, the diff between the files is shown, and the user picks the
+/// direction, with `direction` as the default. Symlinks under `local_root` are stored
+/// relative to it, see `util::symlink_target`.
+fn sync_files<P: AsRef<Path>, Q: AsRef<Path>>(
+  remote_file: P,
+  local_file: Q,
+  local_root: &Path,
+  direction: SyncDirection,
+  reason: &str,
+  file_state: Option<&FileState>,
+  interactive: bool,
+) -> Result<SyncResult> {
+  use SyncDirection::*;
+
+  let (remote_file, local_file) = (remote_file.as_ref(), local_file.as_ref());
+
+  let (direction, reason) = if interactive && direction != NoDiff {
+    match prompt(remote_file, local_file, &direction)? {
+      None => return Ok(SyncResult::Skipped),
+      Some(direction) => {
+        let reason = format!("kept {}", direction.source());
+        (direction, reason)
+      }
+    }
+  } else {
+    (direction, reason.to_owned())
+  };
+
+  if direction != Merge {
+    return copy(remote_file, local_file, local_root, &direction, &reason);
+  }
+
+  if remote_file.is_symlink() || local_file.is_symlink() {
+    util::warn(format!(
+      "skipping {}, both sides changed and symlinks can't be merged",
+      color::path(local_file)
+    ));
+    return Ok(SyncResult::Skipped);
+  }
+
+  let base = match file_state {
+    Some(file_state) => git::show(&file_state.commit, remote_file)?,
+    None => None,
+  };
+
+  match (base, interactive) {
+    (base, true) => edit_merge(remote_file, local_file, &base.unwrap_or_default()),
+    (Some(base), false) => merge(remote_file, local_file, &base),
+    (None, false) => {
+      util::warn(format!(
+        "skipping {}, both sides changed and its last synced version is missing",
+        color::path(local_file)
+      ));
+      Ok(SyncResult::Skipped)
+    }
+  }
+}
+
+/// Shows the diff between `remote_file` and `local_file`, and asks the user which
+/// direction to sync them in, defaulting to `default`. Returns `None` if the user
+/// chooses to skip these files.
+fn prompt(
+  remote_file: &Path,
+  local_file: &Path,
+  default: &SyncDirection,
+) -> Result<Option<SyncDirection>> {
+  use SyncDirection::*;
+
+  if let Some(diff) = util::diff(remote_file, local_file)? {
+    util::info(format!(
+      "diff {}\n{}\n",
+      color::path(remote_file.strip_prefix(config::tittle_config_dir())?),
+      diff
+    ));
+  }
+
+  let default_choice = match default {
+    FromRemote => "r",
+    ToRemote => "l",
+    Merge | NoDiff => "m",
+  };
+
+  loop {
+    let choice = util::prompt(format!(
+      "keep [l]ocal, keep [r]emote, [m]erge in $EDITOR, or [s]kip? ({}) ",
+      color::emphasis(default_choice)
+    ))?;
+
+    match choice.as_str() {
+      "" => return Ok(Some(default.clone())),
+      "l" => return Ok(Some(ToRemote)),
+      "r" => return Ok(Some(FromRemote)),
+      "m" => return Ok(Some(Merge)),
+      "s" => return Ok(None),
+      _ => continue,
+    }
+  }
+}
+
+/// Copies `remote_file` onto `local_file` or vice versa, depending on `direction`,
+/// logging the `reason` this direction was chosen. If the file being copied doesn't
+/// exist, then it was deleted, and the other file is deleted as well. Symlinks are
+/// copied as symlinks, and a local symlink whose target is stored relative to
+/// `local_root` is replaced with the stored symlink.
+fn copy<P: AsRef<Path>, Q: AsRef<Path>>(
+  remote_file: P,
+  local_file: Q,
+  local_root: &Path,
+  direction: &SyncDirection,
+  reason: &str,
+) -> Result<SyncResult> {
+  let (remote_file, local_file) = (remote_file.as_ref(), local_file.as_ref());
+
+  let (from, to, arrow_str) = match direction {
+    SyncDirection::NoDiff => return Ok(SyncResult::Synced),
+    SyncDirection::Merge => unreachable!("merges are not copies"),
+    SyncDirection::FromRemote => (remote_file, local_file, "->"),
+    SyncDirection::ToRemote => (local_file, remote_file, "<-"),
+  };
+
+  let deleted = !util::exists(from);
+
+  match (direction, deleted) {
+    (SyncDirection::FromRemote, true) => local::remove(local_file)?,
+    (SyncDirection::FromRemote, false) => local::copy(remote_file, local_file)?,
+    _ if util::dry_run() => (),
+    (_, true) => fs::remove_file(remote_file)?,
+    (_, false) if local_file.is_symlink() => {
+      util::copy_symlink(local_file, remote_file, local_root)?;
+      if fs::read_link(remote_file)? != fs::read_link(local_file)? {
+        local::symlink(fs::read_link(remote_file)?, local_file)?;
+      }
+    }
+    (_, false) => {
+      // copying onto a symlink would write to its target
+      if remote_file.is_symlink() {
+        fs::remove_file(remote_file)?;
+      }
+      util::create_parent(remote_file)?;
+      local::fetch(local_file, remote_file)?;
+    }
+  }
+
+  if deleted {
+    util::info(format!(
+      "delete {} ({} was deleted)",
+      color::path(to),
+      color::path(from)
+    ));
+    return Ok(SyncResult::Removed);
+  }
+
+  util::info(format!(
+    "sync {} {} {} ({})",
+    color::path(remote_file),
+    arrow_str,
+    color::path(local_file),
+    reason
+  ));
+
+  Ok(SyncResult::Synced)
+}
+
+/// Merges the changes made to `remote_file` and `local_file` since they were both
+/// `base`, writing the result to both files. If the merge has conflicts, then the
+/// local file receives the conflict markers and the remote file is left untouched.
+fn merge<P: AsRef<Path>, Q: AsRef<Path>>(
+  remote_file: P,
+  local_file: Q,
+  base: &[u8],
+) -> Result<SyncResult> {
+  let (remote_file, local_file) = (remote_file.as_ref(), local_file.as_ref());
+
+  let (contents, conflict) = match merge_contents(remote_file, local_file, base) {
+    Ok(merged) => merged,
+    Err(err) => {
+      util::warn(format!(
+        "skipping {}, both sides changed and {}",
+        color::path(local_file),
+        err
+      ));
+      return Ok(SyncResult::Skipped);
+    }
+  };
+
+  local::write(local_file, &contents)?;
+
+  if conflict {
+    util::warn(format!(
+      "conflict {} <> {}, resolve the conflict markers in the local file and sync again",
+      color::path(remote_file),
+      color::path(local_file)
+    ));
+    return Ok(SyncResult::Conflict);
+  }
+
+  if !util::dry_run() {
+    fs::write(remote_file, &contents)?;
+  }
+
+  util::info(format!(
+    "merge {} <> {}",
+    color::path(remote_file),
+    color::path(local_file)
+  ));
+
+  Ok(SyncResult::Synced)
+}
+
+/// Opens the merge of `remote_file` and `local_file` in `$EDITOR`, and writes the
+/// edited result to both files. The merge starts out as the three-way merge against
+/// `base`, so any conflicts are marked with conflict markers.
+fn edit_merge<P: AsRef<Path>, Q: AsRef<Path>>(
+  remote_file: P,
+  local_file: Q,
+  base: &[u8],
+) -> Result<SyncResult> {
+  let (remote_file, local_file) = (remote_file.as_ref(), local_file.as_ref());
+
+  let (contents, _) = merge_contents(remote_file, local_file, base)?;
+
+  let mut merge_path = env::temp_dir();
+  merge_path.push(format!(
+    "tittle-merge-{}-{}",
+    std::process::id(),
+    local_file.file_name().unwrap().to_string_lossy()
+  ));
+  fs::write(&merge_path, contents)?;
+
+  Command::new(edit::editor()?).arg(&merge_path).status()?;
+
+  let unresolved = has_conflict_markers(&merge_path)?;
+  let contents = fs::read(&merge_path)?;
+  fs::remove_file(&merge_path)?;
+
+  if unresolved {
+    util::warn(format!(
+      "skipping {}, the merge still has conflict markers",
+      color::path(local_file)
+    ));
+    return Ok(SyncResult::Skipped);
+  }
+
+  local::write(local_file, &contents)?;
+  if !util::dry_run() {
+    fs::write(remote_file, &contents)?;
+  }
+
+  util::info(format!(
+    "merge {} <> {} (edited)",
+    color::path(remote_file),
+    color::path(local_file)
+  ));
+
+  Ok(SyncResult::Synced)
+}
+
+/// Returns the three-way merge of `remote_file` and `local_file` against `base`, and
+/// whether it has conflicts. A file that doesn't exist is merged as if it were empty.
+fn merge_contents(
+  remote_file: &Path,
+  local_file: &Path,
+  base: &[u8],
+) -> Result<(Vec<u8>, bool)> {
+  let merge_dir = env::temp_dir().join(format!("tittle-merge-{}", std::process::id()));
+  fs::create_dir_all(&merge_dir)?;
+
+  let (local, base_file, remote) = (
+    merge_dir.join("local"),
+    merge_dir.join("base"),
+    merge_dir.join("remote"),
+  );
+  fs::write(&local, read_if_exists(local_file)?.unwrap_or_default())?;
+  fs::write(&base_file, base)?;
+  fs::write(&remote, read_if_exists(remote_file)?.unwrap_or_default())?;
+
+  let merged = git::merge_file(&local, &base_file, &remote);
+  fs::remove_dir_all(&merge_dir)?;
+
+  merged
+}
+
+/// Returns the contents of `path`, or `None` if it doesn't exist.
+fn read_if_exists<P: AsRef<Path>>(path: P) -> Result<Option<Vec<u8>>> {
+  let path = path.as_ref();
+  if path.exists() {
+    Ok(Some(local::read(path)?))
+  } else {
+    Ok(None)
+  }
+}
+
+/// Returns whether `path` contains any conflict markers left by `merge`.
+fn has_conflict_markers<P: AsRef<Path>>(path: P) -> Result<bool> {
+  let path = path.as_ref();
+  if !path.is_file() || path.is_symlink() {
+    return Ok(false);
+  }
+
+  Ok(
+    String::from_utf8_lossy(&local::read(path)?)
+      .lines()
+      .any(|line| {
+        line.starts_with("<<<<<<< local") || line.starts_with(">>>>>>> remote")
+      }),
+  )
+}
+
+/// Returns the pairs of corresponding files under a tracked file or directory. For
+/// a tracked directory, both the remote and local directories are walked, so a file
+/// that exists on only one side still appears in a pair. The other path of such a
+/// pair does not exist. Files ignored by the entry's ignore patterns are skipped.
 ///
 /// # Arguments
 ///
-/// * `remote` - A key from `Config::dest`.
+/// * `remote` - A key from `Config::dest`, or a path inside one.
 /// * `local` - The corresponding value to the key `remote`.
 pub fn remote_and_local_files<P: AsRef<Path>, Q: AsRef<Path>>(
   remote: P,
   local: Q,
 ) -> Result<Vec<(PathBuf, PathBuf)>> {
-  let remote = remote.as_ref();
+  let name = remote.as_ref();
   let local = local.as_ref();
 
   let tittle_config_dir = config::tittle_config_dir();
-  let remote = &tittle_config_dir.join(remote);
+  let remote = &tittle_config_dir.join(name);
 
-  if remote.is_file() {
+  if !remote.is_dir() && !local.is_dir() {
     return Ok(vec![(remote.to_path_buf(), local.to_path_buf())]);
   }
 
-  let mut vec = Vec::new();
-
-  for remote_file in WalkDir::new(remote).into_iter() {
-    let remote_file = remote_file?;
-    let remote_file = remote_file.path();
+  let config = config::get_config()?;
+  let ignore = Ignore::new(&config, name)?;
+  let entry = config
+    .entry_of(name)
+    .unwrap_or_else(|| name.to_string_lossy().to_string());
+  let nested = config.nested_entries(entry);
+  let mut files = BTreeSet::new();
 
-    if remote_file.is_dir() {
+  for root in &[remote, local] {
+    if !root.is_dir() {
       continue;
     }
 
-    let local_file = local.join(remote_file.strip_prefix(&remote)?);
+    let walk = WalkDir::new(root).into_iter().filter_entry(|file| {
+      let relative = file.path().strip_prefix(root).unwrap_or(file.path());
+      let remote_file = name.join(relative);
+
+      // files of another entry inside this one are synced by that entry
+      !ignore.is_ignored(&remote_file)
+        && !nested.iter().any(|other| remote_file.starts_with(other))
+    });
+
+    for file in walk {
+      // symlinks are never followed, so they are synced as files
+      let file = file?;
+      if file.depth() == 0 || file.file_type().is_dir() {
+        continue;
+      }
+
+      let file = file.path();
 
-    vec.push((remote_file.to_path_buf(), local_file.to_path_buf()));
+      files.insert(file.strip_prefix(root)?.to_path_buf());
+    }
   }
 
-  return Ok(vec);
+  Ok(
+    files
+      .into_iter()
+      .map(|file| (remote.join(&file), local.join(&file)))
+      .collect(),
+  )
 }
 
 /// Returns a file's timestamp in seconds. If the file does not exist then return 0.
 fn file_timestamp<P: AsRef<Path>>(path: P) -> u64 {
-  if !path.as_ref().exists() {
+  if !util::exists(&path) {
     return 0;
   }
 
-  fs::metadata(path)
+  fs::symlink_metadata(path)
     .unwrap()
     .modified()
     .unwrap()
@@ -117,40 +722,117 @@ fn file_timestamp<P: AsRef<Path>>(path: P) -> u64 {
     .as_secs()
 }
 
-/// Returns which direction to sync this sequence of `(remote, local)` files.
+/// Returns a remote file's timestamp in seconds, which is the time of the most recent
+/// commit modifying it. If it was never committed, its filesystem timestamp is used.
+fn remote_timestamp<P: AsRef<Path>>(path: P) -> Result<u64> {
+  match git::timestamp(&path)? {
+    0 => Ok(file_timestamp(path)),
+    timestamp => Ok(timestamp),
+  }
+}
+
+/// Returns which direction to sync this sequence of `(remote, local)` files, and the
+/// reason for it.
 ///
 /// This function assumes that this is an return value of `remote_and_local_files`.
 /// If no diffs occur between the pairs of files in `files`, then `NoDiff` will be
-/// returned. If the remote files contain the newest file, then `FromRemote`
-/// is returned. Otherwise, `ToRemote` is returned.
-fn sync_direction<P: AsRef<Path>, Q: AsRef<Path>>(files: &[(P, Q)]) -> SyncDirection {
+/// returned. Files that were synced before are compared against the hashes in
+/// `state`, and if only the remote or only the local files changed, then the changed
+/// side is returned. Otherwise, timestamps are compared for the remaining files. If
+/// the remote files contain the newest file, then `FromRemote` is returned. Otherwise,
+/// `ToRemote` is returned.
+fn sync_direction<P: AsRef<Path>, Q: AsRef<Path>>(
+  files: &[(P, Q)],
+  state: &State,
+) -> Result<(SyncDirection, String)> {
   use SyncDirection::*;
 
-  let (remote_time, local_time, diff) = files
-    .iter()
-    .map(|(remote_f, local_f)| {
-      (
-        git::timestamp(remote_f).unwrap(),
-        file_timestamp(local_f),
-        util::diff(remote_f, local_f).unwrap(),
-      )
-    })
-    .fold(
-      (0, 0, false),
-      |(r_acc, l_acc, d_acc), (r_time, l_time, diff)| {
-        (
-          max(r_acc, r_time),
-          max(l_acc, l_time),
-          d_acc | (None != diff),
-        )
-      },
-    );
-
-  if !diff {
-    NoDiff
+  let (mut remote_time, mut local_time, mut diff) = (0, 0, false);
+  let (mut remote_changed, mut local_changed) = (false, false);
+
+  for (remote_f, local_f) in files.iter() {
+    diff |= util::diff(remote_f, local_f)?.is_some();
+
+    match state.get(remote_f) {
+      Some(file_state) => {
+        remote_changed |= file_state.changed(remote_f)?;
+        local_changed |= file_state.changed(local_f)?;
+      }
+      None => {
+        remote_time = max(remote_time, remote_timestamp(remote_f)?);
+        local_time = max(local_time, file_timestamp(local_f));
+      }
+    }
+  }
+
+  let entry = if files.len() > 1 { " entry" } else { "" };
+
+  let (direction, reason) = if !diff {
+    (NoDiff, "")
+  } else if remote_changed && !local_changed {
+    (FromRemote, "changed")
+  } else if local_changed && !remote_changed {
+    (ToRemote, "changed")
   } else if remote_time > local_time {
-    FromRemote
+    (FromRemote, "is newer")
   } else {
-    ToRemote
+    (ToRemote, "is newer")
+  };
+
+  let reason = format!("{}{} {}", direction.source(), entry, reason);
+
+  Ok((direction, reason))
+}
+
+/// Returns the direction to sync `remote_file` and `local_file` in, and why. This is
+/// the `entry_direction` if one was chosen for the whole entry, otherwise it depends
+/// on whether the files were synced before, see `sync_direction` and
+/// `merge_direction`.
+fn file_direction(
+  remote_file: &Path,
+  local_file: &Path,
+  entry_direction: &Option<(SyncDirection, String)>,
+  state: &State,
+) -> Result<(SyncDirection, String)> {
+  match (entry_direction, state.get(remote_file)) {
+    (Some((direction, reason)), _) => Ok((direction.clone(), reason.clone())),
+    (None, None) => sync_direction(&[(remote_file, local_file)], state),
+    (None, Some(file_state)) => {
+      let direction = merge_direction(remote_file, local_file, file_state)?;
+      let reason = format!("{} changed", direction.source());
+      Ok((direction, reason))
+    }
   }
 }
+
+/// Returns which direction to sync a `(remote_file, local_file)` pair, given the
+/// `file_state` from when it was last synced. If both files changed since then,
+/// `Merge` is returned. A deleted file counts as a change, unless the other file
+/// changed as well, in which case the changed file is kept.
+fn merge_direction<P: AsRef<Path>, Q: AsRef<Path>>(
+  remote_file: P,
+  local_file: Q,
+  file_state: &FileState,
+) -> Result<SyncDirection> {
+  use SyncDirection::*;
+
+  let (remote_file, local_file) = (remote_file.as_ref(), local_file.as_ref());
+
+  if state::hash(remote_file)? == state::hash(local_file)? {
+    return Ok(NoDiff);
+  }
+
+  Ok(
+    match (
+      file_state.changed(remote_file)?,
+      file_state.changed(local_file)?,
+    ) {
+      (true, false) => FromRemote,
+      (false, true) => ToRemote,
+      // the remote file changed and the local file was deleted, keep the changes
+      _ if !local_file.exists() => FromRemote,
+      _ if !remote_file.exists() => ToRemote,
+      _ => Merge,
+    },
+  )
+}
@@ -0,0 +1,183 @@
+use crate::cmd::{edit, sync};
+use crate::config::{self, Config, Mode};
+use crate::util::{self, color};
+use crate::{err, git, local, perms, state};
+
+use anyhow::Result;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Turns the tracked file `name` into a template. The file is opened in `$EDITOR`,
+/// where values are marked as variables by replacing them with `{{var=value}}`. The
+/// marked values are saved as this machine's variables, and the file becomes the
+/// template `name.template`, tracked next to its local file, which it renders to.
+pub fn templatize(name: &str) -> Result<()> {
+  let mut config = config::get_config()?;
+  let name = file_entry(&config, name)?;
+  let template = format!("{}.template", name);
+
+  let tittle_config_dir = config::tittle_config_dir();
+  let remote = tittle_config_dir.join(&name);
+  let remote_template = tittle_config_dir.join(&template);
+  let local = PathBuf::from(config.dest(&name));
+  let local_template = PathBuf::from(format!("{}.template", local.display()));
+
+  for path in [&remote_template, &local_template] {
+    if util::exists(path) {
+      return err::err(format!(
+        "Can't create template '{}', it already exists",
+        path.display()
+      ));
+    }
+  }
+
+  let source = if local.is_file() { &local } else { &remote };
+  let (contents, vars) = mark_in_editor(&name, &fs::read_to_string(source)?)?;
+  if vars.is_empty() {
+    return err::err("No values were marked, not templatizing");
+  }
+
+  let my_vars = config.vars();
+  for (var, value) in &vars {
+    match my_vars.get(var) {
+      Some(old) if old.to_string() != *value => util::warn(format!(
+        "variable {} was {}, it is now {}",
+        color::emphasis(var),
+        color::emphasis(old.to_string()),
+        color::emphasis(value)
+      )),
+      _ => (),
+    }
+
+    config.set_var(var, value.clone())?;
+  }
+
+  // templates are always copied, so a symlink to the remote file is replaced by a copy
+  if local::is_linked(&remote, &local) {
+    local::unlink(&local)?;
+    local::copy(&remote, &local)?;
+  }
+
+  let mut state = state::get_state()?;
+  let mut permissions = perms::get_permissions()?;
+  state.rename(&remote, &remote_template);
+  permissions.rename(&remote, &remote_template);
+
+  if !util::dry_run() {
+    git::mv(&name, &template)?;
+    fs::write(&remote_template, &contents)?;
+  }
+
+  local::write(&local_template, &contents)?;
+  if let Some(mode) = perms::mode(source)? {
+    local::set_mode(&local_template, mode)?;
+  }
+
+  config.templatize(&name, &template);
+  config.set_mode(&template, Mode::Copy);
+
+  util::info(format!(
+    "template {} renders to {}, with variables {}",
+    color::path(&local_template),
+    color::path(&local),
+    color::emphasis(vars.into_keys().collect::<Vec<_>>().join(", "))
+  ));
+
+  config::write_config(&config)?;
+  perms::write_permissions(&permissions)?;
+
+  git::commit(&env::args().collect::<Vec<String>>()[1..].join(" "))?;
+
+  // the template starts out in sync
+  let head = git::head()?;
+  for (remote_file, _) in sync::remote_and_local_files(&template, &local_template)? {
+    state.synced(remote_file, &head)?;
+  }
+
+  state::write_state(&state)
+}
+
+/// Returns the entry `name`, which must be a single tracked file that isn't already a
+/// template.
+fn file_entry(config: &Config, name: &str) -> Result<String> {
+  let name = name.trim_end_matches('/');
+  if !config.has_remote(name) {
+    return match config.entry_of(name) {
+      Some(entry) => err::err(format!(
+        "'{}' is inside the directory '{}', only tracked files can become templates",
+        color::emphasis(name),
+        color::emphasis(entry)
+      )),
+      None => err::err(format!(
+        "The name '{}' is not being tracked",
+        color::emphasis(name)
+      )),
+    };
+  }
+
+  if config::tittle_config_dir().join(name).is_dir() {
+    return err::err(format!(
+      "'{}' is a directory, only tracked files can become templates",
+      color::emphasis(name)
+    ));
+  }
+
+  if config.templates().contains_key(name) {
+    return err::err(format!("'{}' is already a template", color::emphasis(name)));
+  }
+
+  Ok(name.to_owned())
+}
+
+/// Opens `contents` in `$EDITOR` to mark values as variables, written as
+/// `{{var=value}}`. Returns the template, where each marked value is replaced with
+/// `{{var}}`, along with the marked variables and their values.
+fn mark_in_editor(
+  name: &str,
+  contents: &str,
+) -> Result<(String, BTreeMap<String, String>)> {
+  use std::io::prelude::*;
+
+  let machine_id = util::machine_id()?;
+  let file_name = Path::new(name).file_name().unwrap().to_string_lossy();
+  let mut temp_path = env::temp_dir();
+  temp_path.push(format!("templatize-{}-{}", machine_id, file_name));
+
+  let mut temp_file = File::create(&temp_path)?;
+  temp_file.write_all(contents.as_bytes())?;
+  temp_file.flush()?;
+
+  util::info(format!(
+    "mark values as variables by replacing them with {}",
+    color::emphasis("{{var=value}}")
+  ));
+
+  Command::new(edit::editor()?).arg(&temp_path).status()?;
+
+  let mut marked = String::new();
+  File::open(&temp_path)?.read_to_string(&mut marked)?;
+  fs::remove_file(&temp_path)?;
+
+  let re = Regex::new(r"\{\{\s*([A-Za-z0-9_]+)\s*=(.*?)\}\}").unwrap();
+  let mut vars = BTreeMap::new();
+  for captures in re.captures_iter(&marked) {
+    let (var, value) = (captures[1].to_owned(), captures[2].to_owned());
+    match vars.get(&var) {
+      Some(other) if other != &value => {
+        return err::err(format!(
+          "Variable {} was marked with different values",
+          color::emphasis(var)
+        ))
+      }
+      _ => vars.insert(var, value),
+    };
+  }
+
+  let template = re.replace_all(&marked, "{{${1}}}").to_string();
+
+  Ok((template, vars))
+}
@@ -1,8 +1,15 @@
+use crate::cmd::sync;
+use crate::config::{self, Config, Mode};
+use crate::ignore::Ignore;
+use crate::secrets::{self, Secret, Secrets};
 use crate::util::{self, color};
-use crate::{config, err, git};
+use crate::{err, git, local, perms, state};
 
 use anyhow::Result;
-use std::{env, fs, path::Path};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
 
 /// Returns a remote name given a local `path`.
 fn infer_name_from_path<P: AsRef<Path>>(path: &P) -> Option<&std::ffi::OsStr> {
@@ -16,9 +23,20 @@ fn infer_name_from_path<P: AsRef<Path>>(path: &P) -> Option<&std::ffi::OsStr> {
 }
 
 /// Returns a remote name given a remote `path` and optionally an override `name`.
-fn infer_name<P: AsRef<Path>>(path: &P, name: Option<&str>) -> Option<String> {
+/// If `path` is under the local path of the directory entry `entry`, then its name is
+/// the matching path under that entry.
+fn infer_name<P: AsRef<Path>>(
+  path: &P,
+  name: Option<&str>,
+  entry: Option<&(String, String)>,
+) -> Option<String> {
   let path = path.as_ref();
 
+  if let Some((remote, local)) = entry {
+    let relative = path.strip_prefix(local).ok()?;
+    return Some(Path::new(remote).join(relative).to_str()?.to_owned());
+  }
+
   let name = match (name, infer_name_from_path(&path)) {
     (Some(name), _) => name,
     (_, Some(osstr_name)) => osstr_name.to_str().unwrap(),
@@ -38,42 +56,359 @@ fn infer_name<P: AsRef<Path>>(path: &P, name: Option<&str>) -> Option<String> {
   }
 }
 
+/// Returns whether the remote `path` is a file, or a directory containing files. Git
+/// doesn't track empty directories, so they don't count as tracked.
+fn has_files<P: AsRef<Path>>(path: P) -> bool {
+  WalkDir::new(path)
+    .into_iter()
+    .filter_map(Result::ok)
+    .any(|file| !file.file_type().is_dir())
+}
+
 /// Copies local `path` to be tracked under the remote directory `name`.
 ///
-/// If `path` is a directory then all of its contents are copied to the remote `name`.
-fn copy<P: AsRef<Path>>(path: P, name: &str) -> Result<()> {
+/// If `path` is a directory then all of its contents are copied to the remote `name`,
+/// except for those that are ignored by `ignore`. Nothing is copied in dry-run mode.
+fn copy<P: AsRef<Path>>(path: P, name: &str, ignore: &Ignore) -> Result<()> {
   let path = path.as_ref();
 
   let dest = config::tittle_config_dir().join(name);
 
-  if path.is_dir() {
-    util::copy_dir(path, dest)?;
+  if util::dry_run() {
+    return Ok(());
+  }
+
+  let copied = if path.is_dir() {
+    util::copy_dir(path, &dest, |relative| {
+      ignore.is_ignored(Path::new(name).join(relative))
+    })
   } else {
-    if !dest.parent().unwrap().exists() {
-      fs::create_dir_all(&dest.parent().unwrap())?;
+    util::create_parent(&dest).and_then(|_| local::fetch(path, &dest))
+  };
+
+  // a partial copy would be committed by the next commit
+  if copied.is_err() {
+    let tittle_config_dir = config::tittle_config_dir();
+    util::remove_all(&dest)?;
+    util::remove_empty_parents(&dest, &tittle_config_dir)?;
+  }
+
+  copied
+}
+
+/// Returns the secrets in the files that tracking the local `path` under the remote
+/// `name` would copy, along with the file each was found in.
+fn find_secrets(
+  path: &Path,
+  name: &str,
+  ignore: &Ignore,
+) -> Result<Vec<(PathBuf, Vec<Secret>)>> {
+  let walk = WalkDir::new(path).into_iter().filter_entry(|file| {
+    let relative = file.path().strip_prefix(path).unwrap_or(file.path());
+    !ignore.is_ignored(Path::new(name).join(relative))
+  });
+
+  let mut found = Vec::new();
+  for file in walk {
+    let file = file?;
+    let secrets = secrets::scan_file(file.path())?;
+    if !secrets.is_empty() {
+      found.push((file.path().to_path_buf(), secrets));
     }
-    fs::copy(path, dest)?;
   }
 
-  Ok(())
+  Ok(found)
 }
 
-/// Track a local `path` under a remote `name`, potentially making it a template.
+/// Refuses to track the local `path` under the remote `name` if its files contain any
+/// secrets, unless secrets are allowed. If `can_templatize` is set and `path` is a
+/// file, the user is offered to replace its secrets with template variables instead,
+/// and the path of the new template is returned, along with the stored secrets from
+/// before. See `templatize_secrets`.
+fn check_secrets(
+  config: &Config,
+  path: &Path,
+  name: &str,
+  ignore: &Ignore,
+  can_templatize: bool,
+) -> Result<Option<(PathBuf, Secrets)>> {
+  if secrets::allow_secrets() {
+    return Ok(None);
+  }
+
+  let found = find_secrets(path, name, ignore)?;
+  if found.is_empty() {
+    return Ok(None);
+  }
+
+  secrets::report(&found);
+
+  if can_templatize && path.is_file() && !util::dry_run() {
+    let answer = util::prompt("Replace them with template variables? [y/N] ")?;
+    if answer.eq_ignore_ascii_case("y") {
+      return templatize_secrets(config, path, &found[0].1).map(Some);
+    }
+  }
+
+  secrets::refuse("track")
+}
+
+/// Writes a template next to the local file `path`, named like `path` with a
+/// `.template` suffix, where each of `found` is replaced by a variable. The variable
+/// names are prompted for, and their values are stored in `.secrets.json`, which is
+/// never committed. Returns the path of the template, which renders to `path`, along
+/// with the stored secrets from before, so that `untemplatize_secrets` can undo this.
+fn templatize_secrets(
+  config: &Config,
+  path: &Path,
+  found: &[Secret],
+) -> Result<(PathBuf, Secrets)> {
+  let mut file_name = path.file_name().unwrap().to_os_string();
+  file_name.push(".template");
+  let template = path.with_file_name(file_name);
+
+  if util::exists(&template) {
+    return err::err(format!(
+      "Can't create template '{}', it already exists",
+      template.display()
+    ));
+  }
+
+  let vars = config.vars();
+  let previous = secrets::get_secrets()?;
+  let mut stored = previous.clone();
+  let mut contents = fs::read_to_string(path)?;
+
+  for secret in found {
+    // the same secret can appear several times, it was already replaced
+    if !contents.contains(&secret.value) {
+      continue;
+    }
+
+    let default = secrets::var_name(secret.kind, |var| {
+      vars.contains_key(var) || stored.vars.contains_key(var)
+    });
+
+    let var = util::prompt(format!(
+      "Variable for the {} on line {} [{}]: ",
+      secret.kind, secret.line, default
+    ))?;
+    let var = if var.is_empty() { default } else { var };
+
+    if !var.chars().all(|c| c.is_alphanumeric() || c == '_') {
+      return err::err(format!("Invalid variable name {}", color::emphasis(var)));
+    }
+
+    contents = contents.replace(&secret.value, &format!("{{{{{}}}}}", var));
+    stored.vars.insert(var, secret.value.clone());
+  }
+
+  local::write(&template, contents)?;
+  if let Some(mode) = perms::mode(path)? {
+    local::set_mode(&template, mode)?;
+  }
+
+  secrets::write_secrets(&stored)?;
+
+  util::info(format!(
+    "template {} keeps its secrets in {}",
+    color::path(&template),
+    color::path(secrets::tittle_secrets_file()),
+  ));
+
+  Ok((template, previous))
+}
+
+/// Deletes the `template` written by `templatize_secrets`, and puts back the `previous`
+/// stored secrets, after tracking failed.
+fn untemplatize_secrets(template: &Path, previous: &Secrets) -> Result<()> {
+  local::remove(template)?;
+  secrets::write_secrets(previous)
+}
+
+/// A path tracked by a single call to `track_path`.
+///
+/// # Fields
+///
+/// * `name` - The remote name the path is tracked under.
+/// * `path` - The canonical local path.
+/// * `new_entry` - Whether the path is a new entry, rather than part of an already
+///                 tracked directory.
+/// * `templatized` - The template written in place of the path because it had secrets,
+///                   along with the stored secrets from before, see
+///                   `templatize_secrets`.
+struct Tracked {
+  name: String,
+  path: PathBuf,
+  new_entry: bool,
+  templatized: Option<(PathBuf, Secrets)>,
+}
+
+/// Track the local `paths` under a remote `name`, potentially making them templates.
+/// Each path can contain braces and globs, like `~/.config/{i3,polybar}` or
+/// `~/.config/fish/*.fish`, which are expanded even if the shell didn't expand them.
+/// All paths are tracked in a single commit. If tracking any of them fails, none of
+/// them are tracked, and the files copied into the tittle repository are deleted,
+/// along with any templates written for their secrets.
+///
 /// The `renders_to` argument points to the path that the template `path` renders to.
 /// If `renders_to` is not `None` then `path` must be pointing to a template file.
-pub fn track<P: AsRef<Path>, Q: AsRef<Path>>(
-  path: P,
+/// The `mode` argument sets how `path` is deployed, and if it is `Mode::Symlink`,
+/// `path` is replaced with a symlink to its remote copy. The `ignore` patterns are
+/// added to the entry's ignore patterns, see `ignore::Ignore`. If `system` is set, the
+/// paths are system files, such as `/etc/hosts`, which are read and written through
+/// the config's `escalate` command when this user isn't allowed to.
+///
+/// If `path` is under an already tracked directory, and neither `name` nor
+/// `renders_to` is set, then `path` is added to that directory's entry instead of
+/// becoming a new entry.
+pub fn track<Q: AsRef<Path>>(
+  paths: Vec<&str>,
   name: Option<&str>,
   renders_to: Option<Q>,
+  mode: Option<Mode>,
+  ignore: Vec<String>,
+  system: bool,
 ) -> Result<()> {
-  let path = path.as_ref();
+  let mut expanded = Vec::new();
+  for path in paths {
+    expanded.extend(util::expand(path)?);
+  }
+
+  if expanded.len() > 1 && renders_to.is_some() {
+    return err::err("--renders_to can only be set when tracking a single PATH");
+  }
+
+  let mut config = config::get_config()?;
+  let mut tracked = Vec::new();
+
+  for path in &expanded {
+    let result = track_path(
+      &mut config,
+      path,
+      name,
+      renders_to.as_ref(),
+      mode,
+      ignore.clone(),
+      system,
+    );
+
+    match result {
+      Ok(path) => tracked.push(path),
+      Err(err) => {
+        rollback(&tracked)?;
+        return Err(err);
+      }
+    }
+  }
+
+  // the config is written first, since listing the files of an entry reads its
+  // ignore patterns from the config
+  config::write_config(&config)?;
+
+  let mut permissions = perms::get_permissions()?;
+  for Tracked { name, path, .. } in &tracked {
+    for (remote_file, local_file) in sync::remote_and_local_files(name, path)? {
+      permissions.record(remote_file, local_file)?;
+    }
+  }
+  perms::write_permissions(&permissions)?;
+
+  // local files are replaced before committing, so that the commit's journal has them
+  for Tracked {
+    name,
+    path,
+    new_entry,
+    ..
+  } in &tracked
+  {
+    relativize_links(name, path)?;
+
+    if *new_entry && config.mode(name) == Mode::Symlink {
+      sync::link(config::tittle_config_dir().join(name), path)?;
+    }
+  }
 
+  git::commit(&env::args().collect::<Vec<String>>()[1..].join(" "))?;
+
+  // the tracked files start out in sync
+  let mut state = state::get_state()?;
+  let head = git::head()?;
+  for Tracked { name, path, .. } in &tracked {
+    for (remote_file, _) in sync::remote_and_local_files(name, path)? {
+      state.synced(remote_file, &head)?;
+    }
+  }
+
+  state::write_state(&state)
+}
+
+/// Deletes the files copied into the tittle repository for the `tracked` paths, along
+/// with the templates written for their secrets, after tracking another path failed.
+fn rollback(tracked: &[Tracked]) -> Result<()> {
+  if util::dry_run() {
+    return Ok(());
+  }
+
+  let tittle_config_dir = config::tittle_config_dir();
+  for Tracked {
+    name, templatized, ..
+  } in tracked.iter().rev()
+  {
+    let remote = tittle_config_dir.join(name);
+    util::remove_all(&remote)?;
+    util::remove_empty_parents(&remote, &tittle_config_dir)?;
+
+    if let Some((template, previous)) = templatized {
+      untemplatize_secrets(template, previous)?;
+    }
+  }
+
+  Ok(())
+}
+
+/// Replaces the local symlinks of the tracked `path` that were stored with a relative
+/// target in the remote `name`, with the same relative symlinks.
+fn relativize_links(name: &str, path: &Path) -> Result<()> {
+  if util::dry_run() {
+    return Ok(());
+  }
+
+  for (remote_file, local_file) in sync::remote_and_local_files(name, path)? {
+    if local_file.is_symlink() && remote_file.is_symlink() {
+      let target = fs::read_link(&remote_file)?;
+      if fs::read_link(&local_file)? != target {
+        local::symlink(target, &local_file)?;
+      }
+    }
+  }
+
+  Ok(())
+}
+
+/// Track a local `path` under a remote `name` in `config`, copying it into the tittle
+/// repository. See `track`.
+fn track_path<Q: AsRef<Path>>(
+  config: &mut Config,
+  path: &Path,
+  name: Option<&str>,
+  renders_to: Option<Q>,
+  mode: Option<Mode>,
+  ignore: Vec<String>,
+  system: bool,
+) -> Result<Tracked> {
   if !path.exists() {
     return err::err(format!("Path does not exist: '{}'", path.display()));
   }
 
   let path = path.canonicalize()?;
 
+  // resolved before anything is copied, so that it can't fail after
+  let renders_to = match renders_to {
+    Some(renders_to) => Some(renders_to.as_ref().canonicalize()?),
+    None => None,
+  };
+
   // Ensure that `renders_to` is set only if path is a file.
   match renders_to {
     Some(_) if path.is_dir() => {
@@ -82,7 +417,52 @@ pub fn track<P: AsRef<Path>, Q: AsRef<Path>>(
     _ => (),
   }
 
-  let name = match infer_name(&path, name) {
+  if renders_to.is_some() && mode == Some(Mode::Symlink) {
+    return err::err("--mode symlink can't be set for templates, they are always copied");
+  }
+
+  if system && mode == Some(Mode::Symlink) {
+    return err::err(
+      "--mode symlink can't be set for system files, they are always copied",
+    );
+  }
+
+  // a symlinked directory is the repository's directory, so nothing can be left out
+  let symlinked = renders_to.is_none()
+    && !system
+    && mode.unwrap_or_else(|| config.default_mode()) == Mode::Symlink;
+  if symlinked && !ignore.is_empty() {
+    return err::err(
+      "--ignore can't be set for entries in symlink mode, their files are all in the \
+       repository",
+    );
+  }
+
+  if path.starts_with(config::tittle_config_dir()) {
+    return err::err(format!(
+      "Path is inside the tittle directory: '{}'",
+      path.display()
+    ));
+  }
+
+  let entry = match (name, &renders_to) {
+    (None, None) => config.find_entry(&path),
+    _ => None,
+  };
+
+  if entry.is_some() && mode.is_some() {
+    return err::err("--mode can't be set for a path inside a tracked directory");
+  }
+
+  if entry.is_some() && !ignore.is_empty() {
+    return err::err("--ignore can't be set for a path inside a tracked directory");
+  }
+
+  if entry.is_some() && system {
+    return err::err("--system can't be set for a path inside a tracked directory");
+  }
+
+  let name = match infer_name(&path, name, entry.as_ref()) {
     Some(name) => name,
     None => {
       return err::err(format!(
@@ -92,15 +472,84 @@ pub fn track<P: AsRef<Path>, Q: AsRef<Path>>(
     }
   };
 
-  let mut config = config::get_config()?;
   let path_string = path.to_string_lossy();
 
-  if config.has_remote(&name) {
+  if let Some((remote, _)) = &entry {
+    if has_files(config::tittle_config_dir().join(&name)) {
+      return err::err(format!(
+        "'{}' is already being tracked under '{}'",
+        path.display(),
+        name
+      ));
+    }
+
+    let ignore = Ignore::new(config, &name)?;
+    if ignore.is_ignored(&name) {
+      return err::err(format!(
+        "'{}' is ignored by the ignore patterns of '{}'",
+        path.display(),
+        remote
+      ));
+    }
+
+    // files inside a directory entry can't become templates
+    check_secrets(config, &path, &name, &ignore, false)?;
+
+    copy(&path, &name, &ignore)?;
+
+    util::info(format!(
+      "tracking {} under {}, in {}",
+      color::path(&path),
+      color::path(&name),
+      color::path(remote),
+    ));
+  } else if config.has_remote(&name) {
     return err::err(format!("The name '{}' is already being tracked", name));
+  } else if let Some(other) = config.overlapping(&name) {
+    return err::err(format!(
+      "The name '{}' overlaps the tracked entry '{}', set another --name",
+      name, other
+    ));
   } else {
-    copy(&path, &name)?;
-
     config.track(name.to_string(), path_string.to_string());
+    config.add_ignore(&name, ignore);
+
+    if system {
+      config.set_system(&name);
+      local::add_system(&path);
+    }
+
+    let ignore = Ignore::new(config, &name)?;
+    let can_templatize = renders_to.is_none() && mode != Some(Mode::Symlink);
+    if let Some((template, previous)) =
+      check_secrets(config, &path, &name, &ignore, can_templatize)?
+    {
+      config.untrack(&name);
+
+      let name = Path::new(&name).parent().and_then(Path::to_str);
+      let tracked = track_path(
+        config,
+        &template,
+        name,
+        Some(&path),
+        mode,
+        Vec::new(),
+        system,
+      );
+
+      return match tracked {
+        Ok(tracked) => Ok(Tracked {
+          templatized: Some((template, previous)),
+          ..tracked
+        }),
+        Err(err) => {
+          untemplatize_secrets(&template, &previous)?;
+          Err(err)
+        }
+      };
+    }
+
+    copy(&path, &name, &ignore)?;
 
     util::info(format!(
       "tracking {} under {}",
@@ -110,23 +559,27 @@ pub fn track<P: AsRef<Path>, Q: AsRef<Path>>(
   }
 
   if let Some(renders_to) = renders_to {
-    config.track_template(
-      name.to_string(),
-      renders_to
-        .as_ref()
-        .canonicalize()?
-        .to_string_lossy()
-        .to_string(),
-    );
+    if system {
+      local::add_system(&renders_to);
+    }
+
+    config.track_template(name.to_string(), renders_to.to_string_lossy().to_string());
 
     util::info(format!(
       "template {} renders to {}",
       color::path(&path),
-      color::path(renders_to),
+      color::path(&renders_to),
     ));
   }
 
-  config::write_config(&config)?;
+  if let Some(mode) = mode {
+    config.set_mode(&name, mode);
+  }
 
-  git::commit(&env::args().collect::<Vec<String>>()[1..].join(" "))
+  Ok(Tracked {
+    name,
+    path,
+    new_entry: entry.is_none(),
+    templatized: None,
+  })
 }
@@ -1,16 +1,45 @@
-use crate::config;
+use crate::{config, ignore};
 
 use anyhow::Result;
 use std::process::Command;
 
-/// Print a tree of all tracked files. Relies on the `tree` utility.
+/// Print a tree of all tracked files. Relies on the `tree` utility. Files matching
+/// ignore patterns without a `/` are hidden, since `tree` can only ignore file names.
 pub fn tree() -> Result<()> {
+  let mut patterns = vec![
+    ".git*",
+    ".backups",
+    ".journal",
+    ".secrets.json",
+    ".state.json",
+    ".tittleignore",
+    "permissions.json",
+    "tittle_config.json",
+  ]
+  .into_iter()
+  .map(str::to_owned)
+  .collect::<Vec<String>>();
+
+  let config = config::get_config()?;
+  let ignored = ignore::global_patterns()?.into_iter().chain(
+    config
+      .dests()
+      .into_keys()
+      .flat_map(|remote| config.ignore(remote)),
+  );
+  for pattern in ignored {
+    let pattern = pattern.trim_end_matches('/');
+    if !pattern.contains('/') && !patterns.iter().any(|p| p == pattern) {
+      patterns.push(pattern.to_owned());
+    }
+  }
+
   Command::new("tree")
     .arg(config::tittle_config_dir())
     .arg("-aC")
     .arg("--noreport")
     .arg("-I")
-    .arg(".git*|tittle_config.json")
+    .arg(patterns.join("|"))
     .status()?;
 
   Ok(())
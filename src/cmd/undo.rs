@@ -0,0 +1,68 @@
+use crate::journal::{self, Contents};
+use crate::util::{self, color};
+use crate::{err, git, local};
+
+use anyhow::Result;
+use std::env;
+
+/// Undoes the last tittle operation, which is the `HEAD` commit of the tittle
+/// repository. Its changes to the repository are reverted, and the local files it
+/// wrote are restored from its journal, along with the sync state. A local file that
+/// changed since the operation is left alone. Since the undo is itself an operation,
+/// undoing twice redoes.
+///
+/// Only operations made by this machine are undone, so that a commit pulled from
+/// another machine isn't silently reverted. In dry-run mode, the files that would be
+/// reverted are listed instead.
+pub fn undo() -> Result<()> {
+  let head = git::head()?;
+  if git::parent(&head)?.is_none() {
+    return err::err("Nothing to undo.");
+  }
+
+  let machine_id = util::machine_id()?;
+  let message = git::message(&head)?;
+  if !journal::exists(&head) && !message.starts_with(&format!("{}: ", machine_id)) {
+    return err::err(format!(
+      "The last commit, {}, wasn't made by this machine, refusing to undo it",
+      color::emphasis(message.lines().next().unwrap_or_default())
+    ));
+  }
+
+  if util::dry_run() {
+    util::info(format!("revert {}", color::emphasis(&message)));
+    for line in git::stat(&head)?.lines() {
+      util::info(line.trim());
+    }
+  } else {
+    git::revert(&head)?;
+  }
+
+  for write in journal::get(&head)?.into_iter().rev() {
+    if journal::contents(&write.path)? != write.new {
+      util::warn(format!(
+        "skipping {}, it changed since it was written",
+        color::path(&write.path)
+      ));
+      continue;
+    }
+
+    // writing through a symlink would write to its target
+    if write.path.is_symlink() {
+      local::remove(&write.path)?;
+    }
+
+    match write.old {
+      Some(Contents::File(old)) => local::write(&write.path, old)?,
+      Some(Contents::Link(target)) => local::symlink(target, &write.path)?,
+      None if util::exists(&write.path) => local::remove(&write.path)?,
+      None => (),
+    }
+
+    util::info(format!("restore {}", color::path(&write.path)));
+  }
+
+  git::commit(&env::args().collect::<Vec<String>>()[1..].join(" "))?;
+
+  journal::restore_state(&head)
+}
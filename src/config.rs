@@ -1,10 +1,11 @@
 use crate::util::{self, color};
+use crate::{err, perms};
 
 use anyhow::Result;
 
 use std::collections::HashMap;
 use std::io::prelude::*;
-use std::path::{self, Path};
+use std::path::{self, Path, PathBuf};
 use std::{env, fs};
 
 use serde::{Deserialize, Serialize};
@@ -24,7 +25,89 @@ use serde::{Deserialize, Serialize};
 pub struct OverrideConfig {
   dest: HashMap<String, String>,
   templates: HashMap<String, String>,
-  vars: HashMap<String, String>,
+  vars: HashMap<String, Var>,
+}
+
+/// The value of a template variable.
+///
+/// * `String` - A string, which is substituted as is.
+/// * `Number` - A number, which is substituted as it is written in the config.
+/// * `Bool` - A boolean, substituted as `true` or `false`.
+/// * `List` - A list of values, which can be looped over with `{{#each}}`.
+/// * `Cmd` - `{"cmd": "..."}`, the output of a shell command, run when rendering.
+/// * `File` - `{"file": "..."}`, the contents of a file, read when rendering.
+///
+/// `Cmd` and `File` are only evaluated when they come from this machine's overrides.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Var {
+  String(String),
+  Number(serde_json::Number),
+  Bool(bool),
+  List(Vec<Var>),
+  Cmd { cmd: String },
+  File { file: String },
+}
+
+impl Var {
+  /// Returns whether the value counts as true in `{{#if}}`. Empty strings, empty lists
+  /// and `false` are false, everything else is true.
+  pub fn is_truthy(&self) -> bool {
+    match self {
+      Var::String(string) => !string.is_empty(),
+      Var::Number(_) => true,
+      Var::Bool(bool) => *bool,
+      Var::List(list) => !list.is_empty(),
+      Var::Cmd { .. } | Var::File { .. } => true,
+    }
+  }
+}
+
+impl std::fmt::Display for Var {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      Var::String(string) => write!(f, "{}", string),
+      Var::Number(number) => write!(f, "{}", number),
+      Var::Bool(bool) => write!(f, "{}", bool),
+      Var::List(list) => {
+        let items: Vec<String> = list.iter().map(Var::to_string).collect();
+        write!(f, "{}", items.join(", "))
+      }
+      Var::Cmd { .. } | Var::File { .. } => {
+        write!(f, "{}", serde_json::to_string(self).unwrap())
+      }
+    }
+  }
+}
+
+impl From<String> for Var {
+  fn from(string: String) -> Var {
+    Var::String(string)
+  }
+}
+
+/// How a tracked entry is deployed to its local path.
+///
+/// * `Copy` - The local path is a copy of the remote path, kept up to date by `sync`.
+/// * `Symlink` - The local path is a symlink to the remote path.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+  #[default]
+  Copy,
+  Symlink,
+}
+
+impl std::str::FromStr for Mode {
+  type Err = anyhow::Error;
+
+  fn from_str(mode: &str) -> Result<Self> {
+    match mode {
+      "copy" => Ok(Mode::Copy),
+      "symlink" => Ok(Mode::Symlink),
+      _ => err::err(format!("Unknown mode '{}'", mode)),
+    }
+  }
 }
 
 /// A struct representing the JSON in `config.json`.
@@ -34,12 +117,41 @@ pub struct OverrideConfig {
 /// * `overrides` - A map from a machine-id to an OverrideConfig.
 /// * `dest` - A map of remote paths to local paths, describing where each dotfile
 ///            or directory is stored on the local filesystem.
+/// * `mode` - The default `Mode` of every entry in `dest`.
+/// * `modes` - A map from keys of `dest` to their `Mode`, overriding `mode`.
 /// * `templates` - A map from remote template paths to their location after rendering.
+/// * `template_modes` - A map from keys of `templates` to the octal permission bits
+///                      of their rendered files, such as `"0600"`.
+/// * `ignore` - A map from keys of `dest` to glob patterns of files inside them that
+///              aren't tracked. See `ignore::Ignore`.
+/// * `system` - The keys of `dest` that are system files, such as `/etc/hosts`, which
+///              are read and written through `escalate` when needed.
+/// * `escalate` - The command that runs commands as root, such as `sudo` or `doas`.
+/// * `vars` - Template variables shared by every machine, which a machine's overrides
+///            can replace. Their commands and files are never evaluated, see `Var`.
 #[derive(Serialize, Deserialize)]
 pub struct Config {
   dest: HashMap<String, String>,
+  #[serde(default)]
+  mode: Mode,
+  #[serde(default)]
+  modes: HashMap<String, Mode>,
   overrides: HashMap<String, OverrideConfig>,
   templates: HashMap<String, String>,
+  #[serde(default)]
+  template_modes: HashMap<String, String>,
+  #[serde(default)]
+  ignore: HashMap<String, Vec<String>>,
+  #[serde(default)]
+  system: Vec<String>,
+  #[serde(default = "default_escalate")]
+  escalate: String,
+  #[serde(default)]
+  vars: HashMap<String, Var>,
+}
+
+fn default_escalate() -> String {
+  "sudo".to_owned()
 }
 
 impl Config {
@@ -93,6 +205,238 @@ impl Config {
     self.dest.insert(remote.into(), local.into());
   }
 
+  /// Stops tracking the entry `remote`, removing it from `dest`, `templates`, and
+  /// every machine's overrides.
+  pub fn untrack<S: Into<String>>(&mut self, remote: S) {
+    let remote = remote.into();
+    self.dest.remove(&remote);
+    self.modes.remove(&remote);
+    self.templates.remove(&remote);
+    self.template_modes.remove(&remote);
+    self.ignore.remove(&remote);
+    self.system.retain(|system| system != &remote);
+
+    for override_config in self.overrides.values_mut() {
+      override_config.dest.remove(&remote);
+      override_config.templates.remove(&remote);
+    }
+  }
+
+  /// Renames the entry `from` to `to`, in `dest`, `templates`, and every machine's
+  /// overrides.
+  pub fn rename<R: Into<String>, S: Into<String>>(&mut self, from: R, to: S) {
+    fn rename_key<V>(map: &mut HashMap<String, V>, from: &str, to: &str) {
+      if let Some(value) = map.remove(from) {
+        map.insert(to.to_owned(), value);
+      }
+    }
+
+    let (from, to) = (from.into(), to.into());
+    rename_key(&mut self.dest, &from, &to);
+    rename_key(&mut self.modes, &from, &to);
+    rename_key(&mut self.templates, &from, &to);
+    rename_key(&mut self.template_modes, &from, &to);
+    rename_key(&mut self.ignore, &from, &to);
+    for system in self.system.iter_mut().filter(|system| **system == from) {
+      to.clone_into(system);
+    }
+
+    for override_config in self.overrides.values_mut() {
+      rename_key(&mut override_config.dest, &from, &to);
+      rename_key(&mut override_config.templates, &from, &to);
+    }
+  }
+
+  /// Turns the file entry `remote` into the template `template`. The template is
+  /// tracked at the local path of `remote` with a `.template` suffix, and renders to
+  /// that local path, on every machine.
+  pub fn templatize<R: Into<String>, S: Into<String>>(&mut self, remote: R, template: S) {
+    fn templatize_dest(
+      dest: &mut HashMap<String, String>,
+      templates: &mut HashMap<String, String>,
+      template: &str,
+    ) {
+      if let Some(local) = dest.get_mut(template) {
+        templates.insert(template.to_owned(), local.clone());
+        local.push_str(".template");
+      }
+    }
+
+    let template = template.into();
+    self.rename(remote, &template);
+
+    templatize_dest(&mut self.dest, &mut self.templates, &template);
+    for override_config in self.overrides.values_mut() {
+      templatize_dest(
+        &mut override_config.dest,
+        &mut override_config.templates,
+        &template,
+      );
+    }
+  }
+
+  /// Sets the local path of the entry `remote` to `local`, either for every machine, or
+  /// only in this machine's overrides if `machine` is set.
+  pub fn retarget<R: Into<String>, S: Into<String>>(
+    &mut self,
+    remote: R,
+    local: S,
+    machine: bool,
+  ) -> Result<()> {
+    let (remote, local) = (remote.into(), local.into());
+    if !machine {
+      self.dest.insert(remote, local);
+      return Ok(());
+    }
+
+    let mut override_config = self.my_overrides();
+    override_config.dest.insert(remote, local);
+    self.set_my_overrides(override_config)
+  }
+
+  /// Returns the directory entry whose local path contains the local `path`, along
+  /// with that local path. This is a reverse lookup over `dests()`.
+  pub fn find_entry<P: AsRef<Path>>(&self, path: P) -> Option<(String, String)> {
+    let path = path.as_ref();
+    self
+      .dests()
+      .into_iter()
+      .filter(|(remote, local)| {
+        path != Path::new(local)
+          && path.starts_with(local)
+          && tittle_config_dir().join(remote).is_dir()
+      })
+      .max_by_key(|(_, local)| local.len())
+  }
+
+  /// Returns the entry containing the remote path `remote`, which is either the name
+  /// of an entry, or a path inside one.
+  pub fn entry_of<P: AsRef<Path>>(&self, remote: P) -> Option<String> {
+    let remote = remote.as_ref();
+    self
+      .dest
+      .keys()
+      .filter(|key| remote.starts_with(key))
+      .max_by_key(|key| key.len())
+      .cloned()
+  }
+
+  /// Returns an entry that overlaps `name`, because one of them is a path inside the
+  /// other, as `app` and `app/.term.yml` do. Both would claim the same remote files.
+  pub fn overlapping<S: AsRef<str>>(&self, name: S) -> Option<String> {
+    let name = Path::new(name.as_ref());
+    let mut overlapping = self
+      .dest
+      .keys()
+      .filter(|key| name.starts_with(key) || Path::new(key).starts_with(name));
+
+    overlapping.next().cloned()
+  }
+
+  /// Returns the entries inside the directory entry `remote`, other than `remote`
+  /// itself. Their files don't belong to `remote`.
+  pub fn nested_entries<S: AsRef<str>>(&self, remote: S) -> Vec<String> {
+    let remote = remote.as_ref();
+    self
+      .entries(remote)
+      .into_iter()
+      .filter(|entry| entry != remote)
+      .collect()
+  }
+
+  /// Returns the ignore patterns of the entry `remote`.
+  pub fn ignore<S: Into<String>>(&self, remote: S) -> Vec<String> {
+    self.ignore.get(&remote.into()).cloned().unwrap_or_default()
+  }
+
+  /// Adds the ignore `patterns` to the entry `remote`.
+  pub fn add_ignore<S: Into<String>>(&mut self, remote: S, patterns: Vec<String>) {
+    if !patterns.is_empty() {
+      self
+        .ignore
+        .entry(remote.into())
+        .or_default()
+        .extend(patterns);
+    }
+  }
+
+  /// Returns the entries tracked under `name`, which is either the name of an entry or
+  /// a directory containing entries, like `tmux` for `tmux/.tmux.conf`.
+  pub fn entries<S: AsRef<str>>(&self, name: S) -> Vec<String> {
+    let name = name.as_ref().trim_end_matches('/');
+    let mut entries: Vec<String> = self
+      .dest
+      .keys()
+      .filter(|key| Path::new(key).starts_with(name))
+      .cloned()
+      .collect();
+
+    entries.sort();
+    entries
+  }
+
+  /// Returns the permission bits that the template `remote` renders with, or `None`
+  /// if they aren't configured.
+  pub fn template_mode<S: Into<String>>(&self, remote: S) -> Result<Option<u32>> {
+    match self.template_modes.get(&remote.into()) {
+      Some(mode) => Ok(Some(perms::parse_mode(mode)?)),
+      None => Ok(None),
+    }
+  }
+
+  /// Returns how the entry `remote` is deployed. Templates and system entries are
+  /// always copied.
+  pub fn mode<S: Into<String>>(&self, remote: S) -> Mode {
+    let remote = remote.into();
+    if self.templates().contains_key(&remote) || self.system.contains(&remote) {
+      return Mode::Copy;
+    }
+
+    *self.modes.get(&remote).unwrap_or(&self.mode)
+  }
+
+  /// Returns how entries are deployed unless `modes` says otherwise.
+  pub fn default_mode(&self) -> Mode {
+    self.mode
+  }
+
+  /// Sets how the entry `remote` is deployed.
+  pub fn set_mode<S: Into<String>>(&mut self, remote: S, mode: Mode) {
+    let remote = remote.into();
+    if mode == self.mode {
+      self.modes.remove(&remote);
+    } else {
+      self.modes.insert(remote, mode);
+    }
+  }
+
+  /// Marks the entry `remote` as a system entry.
+  pub fn set_system<S: Into<String>>(&mut self, remote: S) {
+    let remote = remote.into();
+    if !self.system.contains(&remote) {
+      self.system.push(remote);
+      self.system.sort();
+    }
+  }
+
+  /// Returns the local paths of system entries on this machine, including the files
+  /// that their templates render to.
+  pub fn system_paths(&self) -> Vec<PathBuf> {
+    let (dests, templates) = (self.dests(), self.templates());
+    self
+      .system
+      .iter()
+      .flat_map(|remote| [dests.get(remote), templates.get(remote)])
+      .flatten()
+      .map(PathBuf::from)
+      .collect()
+  }
+
+  /// Returns the command that runs commands as root, such as `sudo`.
+  pub fn escalate(&self) -> &str {
+    &self.escalate
+  }
+
   pub fn has_remote<S: Into<String>>(&self, remote: S) -> bool {
     self.dest.contains_key(&remote.into())
   }
@@ -116,9 +460,38 @@ impl Config {
     Ok(())
   }
 
-  pub fn vars(&self) -> HashMap<String, String> {
+  /// Returns the template variables of this machine, which are the shared variables
+  /// updated with those in this machine's overrides.
+  pub fn vars(&self) -> HashMap<String, Var> {
+    let mut vars = self.vars.clone();
+    vars.extend(self.my_overrides().vars);
+    vars
+  }
+
+  /// Returns the template variables shared by every machine.
+  pub fn shared_vars(&self) -> HashMap<String, Var> {
+    self.vars.clone()
+  }
+
+  /// Returns the template variables in this machine's overrides.
+  pub fn machine_vars(&self) -> HashMap<String, Var> {
     self.my_overrides().vars
   }
+
+  pub fn set_shared_vars(&mut self, vars: HashMap<String, Var>) {
+    self.vars = vars;
+  }
+
+  /// Sets the variable `var` to `value` in this machine's overrides.
+  pub fn set_var<R: Into<String>, S: Into<Var>>(
+    &mut self,
+    var: R,
+    value: S,
+  ) -> Result<()> {
+    let mut override_config = self.my_overrides();
+    override_config.vars.insert(var.into(), value.into());
+    self.set_my_overrides(override_config)
+  }
 }
 
 fn update_hash_map(
@@ -156,8 +529,15 @@ fn create_config_if_not_exists() -> Result<()> {
   if !config_file.exists() {
     let config = Config {
       dest: HashMap::new(),
+      mode: Mode::default(),
+      modes: HashMap::new(),
       overrides: HashMap::new(),
       templates: HashMap::new(),
+      template_modes: HashMap::new(),
+      ignore: HashMap::new(),
+      system: Vec::new(),
+      escalate: default_escalate(),
+      vars: HashMap::new(),
     };
 
     writeln!(
@@ -187,8 +567,12 @@ pub fn get_config() -> Result<Config> {
   Ok(config)
 }
 
-/// Saves the config `config` to `config.json`.
+/// Saves the config `config` to `config.json`. Does nothing in dry-run mode.
 pub fn write_config(config: &Config) -> Result<()> {
+  if util::dry_run() {
+    return Ok(());
+  }
+
   let config_file = tittle_config_file();
   writeln!(
     fs::File::create(&config_file)?,
@@ -0,0 +1,155 @@
+use crate::config::{self, Config};
+use crate::err;
+use crate::util::color;
+
+use anyhow::Result;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The ignore patterns of a tracked entry, which come from its `ignore` list in the
+/// config, and from the repo-wide `.tittleignore` file.
+///
+/// A pattern without a `/`, such as `*.log`, matches a file or directory with that
+/// name anywhere in the entry. A pattern with a `/`, such as `cache/*`, matches paths
+/// relative to the root of the entry. Everything under an ignored directory is ignored.
+///
+/// # Fields
+///
+/// * `entry` - The remote name of the entry.
+/// * `globs` - The compiled patterns.
+pub struct Ignore {
+  entry: PathBuf,
+  globs: GlobSet,
+}
+
+impl Ignore {
+  /// Returns the ignore patterns of the entry containing the remote path `remote`,
+  /// which is either the name of an entry, or a path inside one.
+  pub fn new<P: AsRef<Path>>(config: &Config, remote: P) -> Result<Ignore> {
+    let remote = remote.as_ref();
+
+    let entry = config
+      .entry_of(remote)
+      .unwrap_or_else(|| remote.to_string_lossy().to_string());
+
+    let mut patterns = global_patterns()?;
+    patterns.extend(config.ignore(&entry));
+
+    Ignore::from_patterns(entry, &patterns)
+  }
+
+  /// Returns the ignore patterns `patterns` of the entry `entry`.
+  fn from_patterns<P: Into<PathBuf>>(entry: P, patterns: &[String]) -> Result<Ignore> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+      let pattern = pattern.trim_end_matches('/');
+      let pattern = match pattern.strip_prefix('/') {
+        Some(anchored) => anchored.to_owned(),
+        None if pattern.contains('/') => pattern.to_owned(),
+        None => format!("**/{}", pattern),
+      };
+
+      for glob in [pattern.clone(), format!("{}/**", pattern)] {
+        match Glob::new(&glob) {
+          Ok(glob) => builder.add(glob),
+          Err(_) => {
+            return err::err(format!("Invalid ignore pattern {}", color::emphasis(glob)))
+          }
+        };
+      }
+    }
+
+    Ok(Ignore {
+      entry: entry.into(),
+      globs: builder.build()?,
+    })
+  }
+
+  /// Returns whether the remote path `remote`, relative to the tittle directory, is
+  /// ignored.
+  pub fn is_ignored<P: AsRef<Path>>(&self, remote: P) -> bool {
+    match remote.as_ref().strip_prefix(&self.entry) {
+      Ok(relative) if !relative.as_os_str().is_empty() => self.globs.is_match(relative),
+      _ => false,
+    }
+  }
+}
+
+/// Returns the path of the `.tittleignore` file.
+pub fn tittle_ignore_file() -> PathBuf {
+  config::tittle_config_dir().join(".tittleignore")
+}
+
+/// Returns the patterns in `.tittleignore`, which apply to every entry. Empty lines
+/// and lines starting with `#` are skipped.
+pub fn global_patterns() -> Result<Vec<String>> {
+  let ignore_file = tittle_ignore_file();
+  if !ignore_file.exists() {
+    return Ok(Vec::new());
+  }
+
+  Ok(
+    fs::read_to_string(ignore_file)?
+      .lines()
+      .map(str::trim)
+      .filter(|line| !line.is_empty() && !line.starts_with('#'))
+      .map(str::to_owned)
+      .collect(),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Returns the ignore patterns `patterns` of the entry `app`.
+  fn ignore(patterns: &[&str]) -> Ignore {
+    let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+    Ignore::from_patterns("app", &patterns).unwrap()
+  }
+
+  #[test]
+  fn matches_names_anywhere() {
+    let ignore = ignore(&["*.log", "cache"]);
+    assert!(ignore.is_ignored("app/a.log"));
+    assert!(ignore.is_ignored("app/sub/dir/a.log"));
+    assert!(ignore.is_ignored("app/cache"));
+    assert!(ignore.is_ignored("app/sub/cache"));
+    assert!(!ignore.is_ignored("app/a.txt"));
+    assert!(!ignore.is_ignored("app/caches"));
+  }
+
+  #[test]
+  fn anchors_patterns_with_slashes() {
+    let ignore = ignore(&["sub/*.txt", "/top"]);
+    assert!(ignore.is_ignored("app/sub/a.txt"));
+    assert!(!ignore.is_ignored("app/other/sub/a.txt"));
+    assert!(ignore.is_ignored("app/top"));
+    assert!(!ignore.is_ignored("app/sub/top"));
+  }
+
+  #[test]
+  fn ignores_everything_under_ignored_directories() {
+    let ignore = ignore(&["cache/", "/state"]);
+    assert!(ignore.is_ignored("app/cache/a"));
+    assert!(ignore.is_ignored("app/sub/cache/a/b"));
+    assert!(ignore.is_ignored("app/state/a/b"));
+    assert!(!ignore.is_ignored("app/sub/state/a"));
+  }
+
+  #[test]
+  fn only_matches_inside_the_entry() {
+    let ignore = ignore(&["*"]);
+    assert!(ignore.is_ignored("app/a"));
+    assert!(!ignore.is_ignored("app"));
+    assert!(!ignore.is_ignored("other/a"));
+    assert!(!ignore.is_ignored("application/a"));
+  }
+
+  #[test]
+  fn rejects_invalid_patterns() {
+    let patterns = vec!["a[".to_owned()];
+    assert!(Ignore::from_patterns("app", &patterns).is_err());
+  }
+}
@@ -0,0 +1,217 @@
+use crate::{config, local, state, util};
+
+use anyhow::Result;
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+/// The local writes made during this run, which are saved when this run commits.
+static WRITES: Mutex<Vec<LocalWrite>> = Mutex::new(Vec::new());
+
+/// A write to a local file.
+///
+/// # Fields
+///
+/// * `path` - The local path that was written.
+/// * `old` - The previous contents of `path`, or `None` if it didn't exist.
+/// * `new` - The new contents of `path`, or `None` if it was deleted.
+pub struct LocalWrite {
+  pub path: PathBuf,
+  pub old: Option<Contents>,
+  pub new: Option<Contents>,
+}
+
+/// What a local path holds.
+///
+/// * `File` - A file with these contents.
+/// * `Link` - A symlink to this target.
+#[derive(PartialEq)]
+pub enum Contents {
+  File(Vec<u8>),
+  Link(PathBuf),
+}
+
+/// A struct representing the JSON in a journal's `index.json`. The contents of each
+/// write are stored alongside it, in files named after the index of the write.
+///
+/// # Fields
+///
+/// * `writes` - The local writes, in the order they were made.
+#[derive(Serialize, Deserialize)]
+struct Index {
+  writes: Vec<IndexEntry>,
+}
+
+/// A write in a journal's `index.json`.
+///
+/// # Fields
+///
+/// * `path` - The local path that was written.
+/// * `old` - Whether `path` had previous contents, stored in `<index>.old`.
+/// * `new` - Whether `path` has new contents, stored in `<index>.new`.
+/// * `old_link` - The previous target of `path`, if it was a symlink.
+/// * `new_link` - The new target of `path`, if it is now a symlink.
+#[derive(Serialize, Deserialize)]
+struct IndexEntry {
+  path: PathBuf,
+  old: bool,
+  new: bool,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  old_link: Option<PathBuf>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  new_link: Option<PathBuf>,
+}
+
+/// Returns the path of the directory holding the journals of each commit.
+pub fn tittle_journal_dir() -> PathBuf {
+  config::tittle_config_dir().join(".journal")
+}
+
+/// Records that the local file `path` is about to be replaced with `new`, or deleted
+/// if `new` is `None`.
+pub fn record<P: AsRef<Path>>(path: P, new: Option<Vec<u8>>) -> Result<()> {
+  record_contents(path.as_ref(), new.map(Contents::File))
+}
+
+/// Records that the local `path` is about to be replaced with a symlink to `target`.
+pub fn record_link<P: AsRef<Path>, Q: AsRef<Path>>(path: P, target: Q) -> Result<()> {
+  record_contents(
+    path.as_ref(),
+    Some(Contents::Link(target.as_ref().to_path_buf())),
+  )
+}
+
+/// Records that the local `path` is about to hold `new`.
+fn record_contents(path: &Path, new: Option<Contents>) -> Result<()> {
+  WRITES.lock().unwrap().push(LocalWrite {
+    path: path.to_path_buf(),
+    old: contents(path)?,
+    new,
+  });
+
+  Ok(())
+}
+
+/// Returns what the local `path` holds, or `None` if it doesn't exist or is a
+/// directory.
+pub fn contents<P: AsRef<Path>>(path: P) -> Result<Option<Contents>> {
+  let path = path.as_ref();
+  if path.is_symlink() {
+    Ok(Some(Contents::Link(fs::read_link(path)?)))
+  } else if path.is_file() {
+    Ok(Some(Contents::File(local::read(path)?)))
+  } else {
+    Ok(None)
+  }
+}
+
+/// Returns whether any local writes were recorded during this run.
+pub fn has_writes() -> bool {
+  !WRITES.lock().unwrap().is_empty()
+}
+
+/// Saves the local writes recorded during this run as the journal of `commit`, along
+/// with the sync state from before `commit`. The journal can hold secrets, so only
+/// this user can read it.
+pub fn save(commit: &str) -> Result<()> {
+  let writes = std::mem::take(&mut *WRITES.lock().unwrap());
+  if writes.is_empty() {
+    return Ok(());
+  }
+
+  let journal_dir = tittle_journal_dir().join(commit);
+  util::create_private_dir(&journal_dir)?;
+
+  // the sync state is written after committing, so this is the state from before
+  let state_file = state::tittle_state_file();
+  if state_file.exists() {
+    fs::copy(state_file, journal_dir.join("state.json"))?;
+  }
+
+  let mut index = Index { writes: Vec::new() };
+  for (i, write) in writes.iter().enumerate() {
+    let mut entry = IndexEntry {
+      path: write.path.clone(),
+      old: false,
+      new: false,
+      old_link: None,
+      new_link: None,
+    };
+
+    match &write.old {
+      Some(Contents::File(old)) => {
+        util::write_with_mode(journal_dir.join(format!("{}.old", i)), old, 0o600)?;
+        entry.old = true;
+      }
+      Some(Contents::Link(target)) => entry.old_link = Some(target.clone()),
+      None => (),
+    }
+    match &write.new {
+      Some(Contents::File(new)) => {
+        util::write_with_mode(journal_dir.join(format!("{}.new", i)), new, 0o600)?;
+        entry.new = true;
+      }
+      Some(Contents::Link(target)) => entry.new_link = Some(target.clone()),
+      None => (),
+    }
+
+    index.writes.push(entry);
+  }
+
+  writeln!(
+    fs::File::create(journal_dir.join("index.json"))?,
+    "{}",
+    serde_json::to_string_pretty(&index)?
+  )?;
+
+  Ok(())
+}
+
+/// Returns whether `commit` has a journal, which means that this machine made it.
+pub fn exists(commit: &str) -> bool {
+  tittle_journal_dir().join(commit).is_dir()
+}
+
+/// Returns the local writes made by `commit`, in the order they were made.
+pub fn get(commit: &str) -> Result<Vec<LocalWrite>> {
+  let journal_dir = tittle_journal_dir().join(commit);
+  let index_file = journal_dir.join("index.json");
+  if !index_file.exists() {
+    return Ok(Vec::new());
+  }
+
+  let index: Index = serde_json::from_str(&fs::read_to_string(index_file)?)?;
+
+  let mut writes = Vec::new();
+  for (i, entry) in index.writes.into_iter().enumerate() {
+    let read = |exists: bool, link: Option<PathBuf>, extension: &str| match (exists, link)
+    {
+      (true, _) => Ok::<_, anyhow::Error>(Some(Contents::File(fs::read(
+        journal_dir.join(format!("{}.{}", i, extension)),
+      )?))),
+      (false, Some(target)) => Ok(Some(Contents::Link(target))),
+      (false, None) => Ok(None),
+    };
+
+    writes.push(LocalWrite {
+      old: read(entry.old, entry.old_link, "old")?,
+      new: read(entry.new, entry.new_link, "new")?,
+      path: entry.path,
+    });
+  }
+
+  Ok(writes)
+}
+
+/// Restores the sync state from before `commit`, if its journal has one.
+pub fn restore_state(commit: &str) -> Result<()> {
+  let state_file = tittle_journal_dir().join(commit).join("state.json");
+  if state_file.exists() && !util::dry_run() {
+    fs::copy(state_file, state::tittle_state_file())?;
+  }
+
+  Ok(())
+}
@@ -0,0 +1,394 @@
+use crate::util::{self, color};
+use crate::{backup, err, journal, perms};
+
+use anyhow::Result;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use walkdir::WalkDir;
+
+/// The local paths of system entries, and the command used to read and write them
+/// when this user isn't allowed to. See `set_system`.
+static SYSTEM: Mutex<(Vec<PathBuf>, String)> = Mutex::new((Vec::new(), String::new()));
+
+/// Sets the local paths of system entries, including the files their templates render
+/// to, along with the `escalate` command, such as `sudo` or `doas`, that runs commands
+/// with the privileges needed to read and write them.
+pub fn set_system(paths: Vec<PathBuf>, escalate: &str) {
+  *SYSTEM.lock().unwrap() = (paths, escalate.to_owned());
+}
+
+/// Adds `path` to the local paths of system entries.
+pub fn add_system<P: AsRef<Path>>(path: P) {
+  SYSTEM.lock().unwrap().0.push(path.as_ref().to_path_buf());
+}
+
+/// Returns whether the local `path` belongs to a system entry.
+pub fn is_system<P: AsRef<Path>>(path: P) -> bool {
+  let path = path.as_ref();
+  SYSTEM
+    .lock()
+    .unwrap()
+    .0
+    .iter()
+    .any(|system| path.starts_with(system))
+}
+
+/// Runs `args` through the escalation command, with `stdin` as its input, and returns
+/// its output.
+fn escalate<S: AsRef<OsStr>>(args: &[S], stdin: Option<&[u8]>) -> Result<Vec<u8>> {
+  let escalate = SYSTEM.lock().unwrap().1.clone();
+  let mut escalate = escalate.split_whitespace();
+  let program = match escalate.next() {
+    Some(program) => program,
+    None => return err::err("No escalation command is configured"),
+  };
+
+  let mut child = Command::new(program)
+    .args(escalate)
+    .args(args)
+    .stdin(if stdin.is_some() {
+      Stdio::piped()
+    } else {
+      Stdio::null()
+    })
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()?;
+
+  if let Some(stdin) = stdin {
+    child.stdin.take().unwrap().write_all(stdin)?;
+  }
+
+  let output = child.wait_with_output()?;
+  if !output.status.success() {
+    util::error(String::from_utf8_lossy(&output.stderr).trim());
+    return err::err(format!(
+      "Couldn't run {} through {}",
+      color::emphasis(
+        args
+          .iter()
+          .map(|arg| arg.as_ref().to_string_lossy())
+          .collect::<Vec<_>>()
+          .join(" ")
+      ),
+      color::emphasis(program)
+    ));
+  }
+
+  Ok(output.stdout)
+}
+
+/// Returns `result` if it succeeded. If it failed because this user isn't allowed to
+/// access the local `path`, and `path` belongs to a system entry, then `escalated` is
+/// run instead.
+fn or_escalate<T, P: AsRef<Path>, F: FnOnce() -> Result<T>>(
+  path: P,
+  result: io::Result<T>,
+  escalated: F,
+) -> Result<T> {
+  match result {
+    Err(err) if err.kind() == io::ErrorKind::PermissionDenied && is_system(path) => {
+      escalated()
+    }
+    result => Ok(result?),
+  }
+}
+
+/// Returns the contents of the local file `path`.
+pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+  let path = path.as_ref();
+  or_escalate(path, fs::read(path), || {
+    escalate(
+      &[OsStr::new("cat"), OsStr::new("--"), path.as_os_str()],
+      None,
+    )
+  })
+}
+
+/// Copies the local file `from` onto the remote file `to`, keeping its modification
+/// time.
+pub fn fetch<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<()> {
+  let (from, to) = (from.as_ref(), to.as_ref());
+  or_escalate(from, util::copy_file(from, to), || {
+    fs::write(to, read(from)?)?;
+    let modified = fs::symlink_metadata(from)?.modified()?;
+    Ok(
+      fs::File::options()
+        .write(true)
+        .open(to)?
+        .set_modified(modified)?,
+    )
+  })
+}
+
+/// Writes `contents` to the local file `path`, creating its parent directories.
+fn write_file(path: &Path, contents: &[u8]) -> Result<()> {
+  let parent = path.parent().unwrap_or(Path::new("/"));
+  or_escalate(path, util::create_parent(path).map_err(into_io), || {
+    escalate(
+      &[
+        OsStr::new("mkdir"),
+        OsStr::new("-p"),
+        OsStr::new("--"),
+        parent.as_os_str(),
+      ],
+      None,
+    )
+    .map(drop)
+  })?;
+
+  or_escalate(path, fs::write(path, contents), || {
+    escalate(
+      &[OsStr::new("tee"), OsStr::new("--"), path.as_os_str()],
+      Some(contents),
+    )
+    .map(drop)
+  })
+}
+
+/// Removes the local file `path`.
+fn remove_file(path: &Path) -> Result<()> {
+  or_escalate(path, fs::remove_file(path), || {
+    escalate(
+      &[
+        OsStr::new("rm"),
+        OsStr::new("-f"),
+        OsStr::new("--"),
+        path.as_os_str(),
+      ],
+      None,
+    )
+    .map(drop)
+  })
+}
+
+/// Returns the `io::Error` underlying `err`, for `or_escalate`.
+fn into_io(err: anyhow::Error) -> io::Error {
+  match err.downcast::<io::Error>() {
+    Ok(err) => err,
+    Err(err) => io::Error::other(err.to_string()),
+  }
+}
+
+/// Writes `contents` to the local file `path`, backing up its previous contents.
+/// Does nothing in dry-run mode.
+pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> Result<()> {
+  let path = path.as_ref();
+  if util::dry_run() {
+    return Ok(());
+  }
+
+  journal::record(path, Some(contents.as_ref().to_vec()))?;
+  backup::backup(path)?;
+  write_file(path, contents.as_ref())
+}
+
+/// Writes `contents` to the local file `path` like `write`, giving it the permission
+/// bits `mode`. The mode is set while the file is still empty, so that the contents
+/// are never readable with other permissions. Does nothing in dry-run mode.
+pub fn write_with_mode<P: AsRef<Path>, C: AsRef<[u8]>>(
+  path: P,
+  contents: C,
+  mode: u32,
+) -> Result<()> {
+  let path = path.as_ref();
+  if util::dry_run() {
+    return Ok(());
+  }
+
+  journal::record(path, Some(contents.as_ref().to_vec()))?;
+  backup::backup(path)?;
+
+  if !util::exists(path) {
+    write_file(path, &[])?;
+  }
+  set_mode(path, mode)?;
+  write_file(path, contents.as_ref())
+}
+
+/// Copies the remote file `from` onto the local file `to`, backing up the previous
+/// contents of `to`. The modification time of `from` is kept, and so is the mode of
+/// `to` if it already exists. If `from` is a symlink, `to` becomes a symlink with the
+/// same target. Does nothing in dry-run mode.
+pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<()> {
+  let (from, to) = (from.as_ref(), to.as_ref());
+  if util::dry_run() {
+    return Ok(());
+  }
+
+  if from.is_symlink() {
+    return symlink(fs::read_link(from)?, to);
+  }
+
+  let contents = fs::read(from)?;
+  journal::record(to, Some(contents.clone()))?;
+  backup::backup(to)?;
+
+  // copying onto a symlink would write to its target
+  if to.is_symlink() {
+    remove_file(to)?;
+  }
+
+  if is_system(to) {
+    // the file is written in place, so that it keeps its owner and mode
+    write_file(to, &contents)?;
+    let modified = fs::metadata(from)?.modified()?;
+    return or_escalate(to, set_modified(to, modified), || {
+      escalate(
+        &[
+          OsStr::new("touch"),
+          OsStr::new("-r"),
+          from.as_os_str(),
+          OsStr::new("--"),
+          to.as_os_str(),
+        ],
+        None,
+      )
+      .map(drop)
+    });
+  }
+
+  util::create_parent(to)?;
+  let mode = perms::mode(to)?;
+  util::copy_file(from, to)?;
+  if let Some(mode) = mode {
+    perms::set_mode(to, mode)?;
+  }
+
+  Ok(())
+}
+
+/// Removes the local file `path`, backing up its contents. Does nothing in dry-run
+/// mode.
+pub fn remove<P: AsRef<Path>>(path: P) -> Result<()> {
+  let path = path.as_ref();
+  if util::dry_run() {
+    return Ok(());
+  }
+
+  journal::record(path, None)?;
+  backup::backup(path)?;
+  remove_file(path)
+}
+
+/// Removes the local symlink `path`, journaling its target so that `undo` can put it
+/// back. Does nothing in dry-run mode.
+pub fn unlink<P: AsRef<Path>>(path: P) -> Result<()> {
+  let path = path.as_ref();
+  if util::dry_run() {
+    return Ok(());
+  }
+
+  journal::record(path, None)?;
+  remove_file(path)
+}
+
+/// Sets the permission bits of the local file `path` to `mode`. Does nothing in
+/// dry-run mode.
+pub fn set_mode<P: AsRef<Path>>(path: P, mode: u32) -> Result<()> {
+  let path = path.as_ref();
+  if util::dry_run() {
+    return Ok(());
+  }
+
+  or_escalate(path, perms::set_mode(path, mode).map_err(into_io), || {
+    escalate(
+      &[
+        OsStr::new("chmod"),
+        OsStr::new(&perms::format_mode(mode)),
+        OsStr::new("--"),
+        path.as_os_str(),
+      ],
+      None,
+    )
+    .map(drop)
+  })
+}
+
+/// Sets the owner and group of the local file `path`, by name. Changing the owner of a
+/// file always needs privileges, so this is always run through the escalation command,
+/// unless tittle is run as root. Does nothing in dry-run mode.
+pub fn set_owner<P: AsRef<Path>>(path: P, owner: &str, group: &str) -> Result<()> {
+  let path = path.as_ref();
+  if util::dry_run() {
+    return Ok(());
+  }
+
+  let owner_group = format!("{}:{}", owner, group);
+  let chown = [
+    OsStr::new("chown"),
+    OsStr::new("-h"),
+    OsStr::new(&owner_group),
+    OsStr::new("--"),
+    path.as_os_str(),
+  ];
+
+  let status = Command::new(chown[0])
+    .args(&chown[1..])
+    .stderr(Stdio::null())
+    .status()?;
+
+  if !status.success() {
+    escalate(&chown, None)?;
+  }
+
+  Ok(())
+}
+
+/// Sets the modification time of `path` to `modified`.
+fn set_modified(path: &Path, modified: std::time::SystemTime) -> io::Result<()> {
+  fs::File::options()
+    .write(true)
+    .open(path)?
+    .set_modified(modified)
+}
+
+/// Returns whether the local `path` is a symlink to `target`.
+pub fn is_linked<P: AsRef<Path>, Q: AsRef<Path>>(target: P, path: Q) -> bool {
+  match fs::read_link(path) {
+    Ok(link) => link == target.as_ref(),
+    Err(_) => false,
+  }
+}
+
+/// Replaces the local `path` with a symlink to `target`. Any files at `path` are
+/// backed up and journaled first, so that `undo` can put them back. Does nothing in
+/// dry-run mode.
+pub fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(target: P, path: Q) -> Result<()> {
+  let (target, path) = (target.as_ref(), path.as_ref());
+  if util::dry_run() {
+    return Ok(());
+  }
+
+  let metadata = fs::symlink_metadata(path);
+  match metadata {
+    Ok(metadata) if metadata.is_dir() => {
+      for file in WalkDir::new(path) {
+        let file = file?;
+        if !file.file_type().is_dir() {
+          journal::record(file.path(), None)?;
+          backup::backup(file.path())?;
+        }
+      }
+      fs::remove_dir_all(path)?;
+      journal::record_link(path, target)?;
+    }
+    Ok(_) => {
+      journal::record_link(path, target)?;
+      backup::backup(path)?;
+      fs::remove_file(path)?;
+    }
+    Err(_) => {
+      journal::record_link(path, target)?;
+      util::create_parent(path)?;
+    }
+  }
+
+  std::os::unix::fs::symlink(target, path)?;
+
+  Ok(())
+}
@@ -1,10 +1,20 @@
+#![allow(clippy::doc_overindented_list_items)]
+
 use anyhow::Result;
 use clap::{App, AppSettings, Arg, SubCommand};
 
+mod backup;
 mod cmd;
 mod config;
 mod err;
 mod git;
+mod ignore;
+mod journal;
+mod local;
+mod perms;
+mod secrets;
+mod state;
+mod template;
 mod util;
 
 fn main() {
@@ -13,6 +23,18 @@ fn main() {
     .author("Enrico Z. Borba <enricozb@gmail.com>")
     .about("Dotfile manager")
     .setting(AppSettings::VersionlessSubcommands)
+    .arg(
+      Arg::with_name("dry_run")
+        .long("dry-run")
+        .global(true)
+        .help("Print the operations that would be performed, without performing them"),
+    )
+    .arg(
+      Arg::with_name("allow_secrets")
+        .long("allow-secrets")
+        .global(true)
+        .help("Track and commit files even if they seem to contain secrets"),
+    )
     .arg(
       Arg::with_name("verbose")
         .short("v")
@@ -42,21 +64,70 @@ fn main() {
         .about("Edit the tittle config")
         .arg(
           Arg::with_name("MODE")
-            .help("One of [me]. Specifies which portion of the config to edit.")
+            .help("One of [me, vars]. Specifies which portion of the config to edit.")
             .index(1),
         ),
     )
+    .subcommand(
+      SubCommand::with_name("mv")
+        .about("Rename a tracked file or directory")
+        .arg(
+          Arg::with_name("OLD")
+            .help("The current name")
+            .required(true)
+            .index(1),
+        )
+        .arg(
+          Arg::with_name("NEW")
+            .help("The new name")
+            .required(true)
+            .index(2),
+        ),
+    )
     .subcommand(SubCommand::with_name("pull").about("Pulls the repository from upstream"))
     .subcommand(
       SubCommand::with_name("push").about("Pushes the current repository upstream"),
     )
     .subcommand(
-      SubCommand::with_name("remove").about("Remove tracked files or directories"),
+      SubCommand::with_name("remove")
+        .about(
+          "Remove tracked files or directories. Opens $EDITOR to choose them if no \
+           NAME is given.",
+        )
+        .arg(
+          Arg::with_name("local")
+            .long("local")
+            .help("Also delete the local files of the removed entries"),
+        )
+        .arg(
+          Arg::with_name("NAME")
+            .help("The names of the entries to remove")
+            .multiple(true)
+            .index(1),
+        ),
     )
     .subcommand(
       SubCommand::with_name("render")
         .about("Render templates to their respective locations"),
     )
+    .subcommand(
+      SubCommand::with_name("restore")
+        .about(
+          "Restore a local file from a backup made before tittle overwrote it. Lists \
+           all backups if PATH is omitted.",
+        )
+        .arg(
+          Arg::with_name("time")
+            .long("time")
+            .value_name("TIME")
+            .help("The time of the backup to restore, defaults to the most recent one"),
+        )
+        .arg(
+          Arg::with_name("PATH")
+            .help("The local path to restore")
+            .index(1),
+        ),
+    )
     .subcommand(
       SubCommand::with_name("repo")
         .about("Sets the upstream dotfile repo")
@@ -68,11 +139,63 @@ fn main() {
         ),
     )
     .subcommand(
-      SubCommand::with_name("sync").about("Sync between remote and local dotfiles"),
+      SubCommand::with_name("retarget")
+        .about("Change the local path of a tracked file or directory")
+        .arg(
+          Arg::with_name("machine")
+            .long("machine")
+            .conflicts_with("global")
+            .help("Only change the local path on this machine, in its overrides"),
+        )
+        .arg(
+          Arg::with_name("global")
+            .long("global")
+            .help("Change the local path on every machine, which is the default"),
+        )
+        .arg(
+          Arg::with_name("move")
+            .long("move")
+            .help("Move the existing local file or directory to the new path"),
+        )
+        .arg(
+          Arg::with_name("NAME")
+            .help("The name of the tracked entry")
+            .required(true)
+            .index(1),
+        )
+        .arg(
+          Arg::with_name("PATH")
+            .help("The new local path")
+            .required(true)
+            .index(2),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("sync")
+        .about("Sync between remote and local dotfiles")
+        .arg(Arg::with_name("whole_entry").long("whole-entry").help(
+          "Sync each tracked directory in a single direction, keeping its newest side",
+        ))
+        .arg(
+          Arg::with_name("interactive")
+            .short("i")
+            .long("interactive")
+            .help("Show the diff of each changed file and choose how to sync it"),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("templatize")
+        .about("Turn a tracked file into a template, marking its variables in $EDITOR")
+        .arg(
+          Arg::with_name("NAME")
+            .help("The name of the tracked file")
+            .required(true)
+            .index(1),
+        ),
     )
     .subcommand(
       SubCommand::with_name("track")
-        .about("Track a file or directory")
+        .about("Track files or directories, in a single commit")
         .arg(
           Arg::with_name("name")
             .short("n")
@@ -87,14 +210,39 @@ fn main() {
             .value_name("PATH")
             .help("If set, then the tracked file is a template and renders to this path"),
         )
+        .arg(
+          Arg::with_name("mode")
+            .short("m")
+            .long("mode")
+            .value_name("MODE")
+            .possible_values(&["copy", "symlink"])
+            .help("How the tracked path is deployed, defaults to the config's mode"),
+        )
+        .arg(Arg::with_name("system").long("system").help(
+          "Track system files, read and written through the config's escalate command",
+        ))
+        .arg(
+          Arg::with_name("ignore")
+            .short("x")
+            .long("ignore")
+            .value_name("PATTERN")
+            .multiple(true)
+            .number_of_values(1)
+            .help("A glob pattern of files in the tracked directory to leave untracked"),
+        )
         .arg(
           Arg::with_name("PATH")
-            .help("The path to track")
+            .help("The paths to track, which can contain braces and globs")
             .required(true)
+            .multiple(true)
             .index(1),
         ),
     )
     .subcommand(SubCommand::with_name("tree").about("Show a tree of the tracked files"))
+    .subcommand(
+      SubCommand::with_name("undo")
+        .about("Undo the last tittle operation on both the repository and local files"),
+    )
     .get_matches();
 
   let run = || -> Result<()> {
@@ -104,33 +252,74 @@ fn main() {
       return Ok(());
     }
 
+    util::set_dry_run(matches.is_present("dry_run"));
+    secrets::set_allow_secrets(matches.is_present("allow_secrets"));
+
     config::init()?;
     git::init()?;
 
+    let config = config::get_config()?;
+    local::set_system(config.system_paths(), config.escalate());
+
     match matches.subcommand() {
       ("diff", _) => cmd::diff::diff()?,
 
       ("edit", Some(matches)) => cmd::edit::edit(matches.value_of("MODE"))?,
 
+      ("mv", Some(matches)) => cmd::mv::mv(
+        matches.value_of("OLD").unwrap(),
+        matches.value_of("NEW").unwrap(),
+      )?,
+
       ("pull", _) => git::pull()?,
       ("push", _) => git::push()?,
 
-      ("remove", _) => cmd::remove::remove()?,
+      ("remove", Some(matches)) => cmd::remove::remove(
+        matches
+          .values_of("NAME")
+          .map_or(Vec::new(), Iterator::collect),
+        matches.is_present("local"),
+      )?,
 
       ("render", _) => cmd::render::render()?,
 
+      ("restore", Some(matches)) => {
+        cmd::restore::restore(matches.value_of("PATH"), matches.value_of("time"))?
+      }
+
       ("repo", Some(matches)) => git::set_remote(matches.value_of("URL").unwrap())?,
 
-      ("sync", _) => cmd::sync::sync()?,
+      ("retarget", Some(matches)) => cmd::retarget::retarget(
+        matches.value_of("NAME").unwrap(),
+        matches.value_of("PATH").unwrap(),
+        matches.is_present("machine"),
+        matches.is_present("move"),
+      )?,
+
+      ("sync", Some(matches)) => cmd::sync::sync(
+        matches.is_present("whole_entry"),
+        matches.is_present("interactive"),
+      )?,
+
+      ("templatize", Some(matches)) => {
+        cmd::templatize::templatize(matches.value_of("NAME").unwrap())?
+      }
 
       ("track", Some(matches)) => cmd::track::track(
-        matches.value_of("PATH").unwrap(),
+        matches.values_of("PATH").unwrap().collect(),
         matches.value_of("name"),
         matches.value_of("renders_to"),
+        matches.value_of("mode").map(str::parse).transpose()?,
+        matches
+          .values_of("ignore")
+          .map_or(Vec::new(), |patterns| patterns.map(str::to_owned).collect()),
+        matches.is_present("system"),
       )?,
 
       ("tree", _) => cmd::tree::tree()?,
 
+      ("undo", _) => cmd::undo::undo()?,
+
       _ => {}
     }
 
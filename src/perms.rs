@@ -0,0 +1,200 @@
+use crate::util::{self, color};
+use crate::{config, err, local};
+
+use anyhow::Result;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::prelude::*;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// The permissions recorded for a single remote file.
+///
+/// # Fields
+///
+/// * `mode` - The file's permission bits, as an octal string such as `"0755"`. Git
+///            only tracks the executable bit, so the full mode is recorded here.
+/// * `owner` - The name of the file's owner, only recorded for system entries.
+/// * `group` - The name of the file's group, only recorded for system entries.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FilePermissions {
+  pub mode: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub owner: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub group: Option<String>,
+}
+
+/// A struct representing the JSON in `permissions.json`. Unlike the sync state, this
+/// file is committed, so permissions are shared by every machine.
+///
+/// # Fields
+///
+/// * `files` - A map from remote file paths, relative to the tittle directory, to
+///             their permissions.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Permissions {
+  files: BTreeMap<String, FilePermissions>,
+}
+
+impl Permissions {
+  /// Returns the recorded mode of `remote_file`, or `None` if none was recorded.
+  pub fn mode<P: AsRef<Path>>(&self, remote_file: P) -> Result<Option<u32>> {
+    match self.files.get(&key(remote_file)) {
+      Some(permissions) => Ok(Some(parse_mode(&permissions.mode)?)),
+      None => Ok(None),
+    }
+  }
+
+  /// Returns the recorded owner and group of `remote_file`, or `None` if none were
+  /// recorded.
+  pub fn owner<P: AsRef<Path>>(&self, remote_file: P) -> Option<(String, String)> {
+    let permissions = self.files.get(&key(remote_file))?;
+    Some((permissions.owner.clone()?, permissions.group.clone()?))
+  }
+
+  /// Records the mode of `local_file` as the mode of `remote_file`, and applies it to
+  /// `remote_file`. Returns whether the recorded mode changed. Symlinks have no mode
+  /// of their own, so they are never recorded. The owner and group of `local_file`
+  /// are recorded as well if it belongs to a system entry, but are never applied to
+  /// `remote_file`, which stays owned by this user.
+  pub fn record<P: AsRef<Path>, Q: AsRef<Path>>(
+    &mut self,
+    remote_file: P,
+    local_file: Q,
+  ) -> Result<bool> {
+    let (remote_file, local_file) = (remote_file.as_ref(), local_file.as_ref());
+    if local_file.is_symlink() {
+      return Ok(false);
+    }
+
+    let mode = match mode(local_file)? {
+      Some(mode) => mode,
+      None => return Ok(false),
+    };
+
+    if !util::dry_run() && remote_file.is_file() {
+      set_mode(remote_file, mode)?;
+    }
+
+    let (owner, group) = match local::is_system(local_file) {
+      true => owner(local_file)?.unzip(),
+      false => (None, None),
+    };
+
+    let changed = self.mode(remote_file)? != Some(mode);
+    self.files.insert(
+      key(remote_file),
+      FilePermissions {
+        mode: format_mode(mode),
+        owner,
+        group,
+      },
+    );
+
+    Ok(changed)
+  }
+
+  /// Moves the record of `from` to `to`, after the remote file was renamed.
+  pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, from: P, to: Q) {
+    if let Some(record) = self.files.remove(&key(from)) {
+      self.files.insert(key(to), record);
+    }
+  }
+
+  /// Forgets `remote_file`, after it was deleted.
+  pub fn forget<P: AsRef<Path>>(&mut self, remote_file: P) {
+    self.files.remove(&key(remote_file));
+  }
+}
+
+/// Returns the permission bits of `path`, or `None` if it doesn't exist.
+pub fn mode<P: AsRef<Path>>(path: P) -> Result<Option<u32>> {
+  match fs::metadata(path) {
+    Ok(metadata) => Ok(Some(metadata.permissions().mode() & 0o7777)),
+    Err(_) => Ok(None),
+  }
+}
+
+/// Returns the names of the owner and group of `path`, or `None` if it doesn't exist.
+/// Ids without a user or group name are returned as numbers.
+pub fn owner<P: AsRef<Path>>(path: P) -> Result<Option<(String, String)>> {
+  use std::os::unix::fs::MetadataExt;
+
+  let metadata = match fs::symlink_metadata(path) {
+    Ok(metadata) => metadata,
+    Err(_) => return Ok(None),
+  };
+
+  let (uid, gid) = (metadata.uid(), metadata.gid());
+  Ok(Some((
+    util::user_name(uid).unwrap_or_else(|| uid.to_string()),
+    util::group_name(gid).unwrap_or_else(|| gid.to_string()),
+  )))
+}
+
+/// Sets the permission bits of `path` to `mode`.
+pub fn set_mode<P: AsRef<Path>>(path: P, mode: u32) -> Result<()> {
+  fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+
+  Ok(())
+}
+
+/// Parses an octal mode string such as `"0600"`.
+pub fn parse_mode(mode: &str) -> Result<u32> {
+  match u32::from_str_radix(mode, 8) {
+    Ok(parsed) if parsed <= 0o7777 => Ok(parsed),
+    _ => err::err(format!("Invalid mode {}", color::emphasis(mode))),
+  }
+}
+
+/// Formats `mode` as an octal mode string such as `"0600"`.
+pub fn format_mode(mode: u32) -> String {
+  format!("{:04o}", mode)
+}
+
+/// Returns the key of `remote_file` in `Permissions::files`.
+fn key<P: AsRef<Path>>(remote_file: P) -> String {
+  let remote_file = remote_file.as_ref();
+  remote_file
+    .strip_prefix(config::tittle_config_dir())
+    .unwrap_or(remote_file)
+    .to_string_lossy()
+    .to_string()
+}
+
+/// Returns the path of the `permissions.json` file.
+pub fn tittle_permissions_file() -> PathBuf {
+  config::tittle_config_dir().join("permissions.json")
+}
+
+/// Returns the Permissions struct representing `permissions.json`, or no permissions
+/// if it doesn't exist.
+pub fn get_permissions() -> Result<Permissions> {
+  let permissions_file = tittle_permissions_file();
+  if !permissions_file.exists() {
+    return Ok(Permissions::default());
+  }
+
+  Ok(serde_json::from_str(&fs::read_to_string(
+    permissions_file,
+  )?)?)
+}
+
+/// Saves `permissions` to `permissions.json`. Does nothing in dry-run mode.
+pub fn write_permissions(permissions: &Permissions) -> Result<()> {
+  if util::dry_run() {
+    return Ok(());
+  }
+
+  writeln!(
+    fs::File::create(tittle_permissions_file())?,
+    "{}",
+    serde_json::to_string_pretty(&permissions)?
+  )?;
+
+  Ok(())
+}
@@ -0,0 +1,174 @@
+use crate::{config, local, perms, util};
+
+use anyhow::Result;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The sync state of a single remote file on this machine.
+///
+/// # Fields
+///
+/// * `commit` - The commit that this file was last synced from. Its contents at this
+///              commit are used as the base of a three-way merge.
+/// * `hash` - The hash of this file's contents when it was last synced.
+/// * `conflict` - Whether the last sync left conflict markers in the local file.
+/// * `mode` - The permission bits of this file when it was last synced.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FileState {
+  pub commit: String,
+  #[serde(default)]
+  pub hash: String,
+  #[serde(default)]
+  pub conflict: bool,
+  #[serde(default)]
+  pub mode: Option<u32>,
+}
+
+impl FileState {
+  /// Returns whether `path` changed since this file was last synced. A file that
+  /// doesn't exist has changed.
+  pub fn changed<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+    Ok(hash(path)?.as_ref() != Some(&self.hash))
+  }
+}
+
+/// A struct representing the JSON in `.state.json`. This file is specific to this
+/// machine and is never committed.
+///
+/// # Fields
+///
+/// * `files` - A map from remote file paths, relative to the tittle directory, to
+///             their sync state.
+#[derive(Serialize, Deserialize, Default)]
+pub struct State {
+  files: HashMap<String, FileState>,
+}
+
+impl State {
+  /// Returns the sync state of `remote_file`, or `None` if it was never synced.
+  pub fn get<P: AsRef<Path>>(&self, remote_file: P) -> Option<&FileState> {
+    self
+      .files
+      .get(&key(remote_file))
+      .filter(|file_state| !file_state.hash.is_empty())
+  }
+
+  /// Records that `remote_file` and its local file were identical at `commit`.
+  pub fn synced<P: AsRef<Path>>(&mut self, remote_file: P, commit: &str) -> Result<()> {
+    self.record(remote_file, commit, false)
+  }
+
+  /// Moves the record of `from` to `to`, after the remote file was renamed.
+  pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, from: P, to: Q) {
+    if let Some(record) = self.files.remove(&key(from)) {
+      self.files.insert(key(to), record);
+    }
+  }
+
+  /// Forgets `remote_file`, after it was deleted from both sides.
+  pub fn forget<P: AsRef<Path>>(&mut self, remote_file: P) {
+    self.files.remove(&key(remote_file));
+  }
+
+  /// Records that merging `remote_file` as of `commit` into its local file left
+  /// conflict markers in the local file.
+  pub fn conflicted<P: AsRef<Path>>(
+    &mut self,
+    remote_file: P,
+    commit: &str,
+  ) -> Result<()> {
+    self.record(remote_file, commit, true)
+  }
+
+  fn record<P: AsRef<Path>>(
+    &mut self,
+    remote_file: P,
+    commit: &str,
+    conflict: bool,
+  ) -> Result<()> {
+    let remote_file = remote_file.as_ref();
+    let hash = match hash(remote_file)? {
+      Some(hash) => hash,
+      None => {
+        self.forget(remote_file);
+        return Ok(());
+      }
+    };
+
+    self.files.insert(
+      key(remote_file),
+      FileState {
+        commit: commit.to_owned(),
+        hash,
+        conflict,
+        mode: perms::mode(remote_file)?,
+      },
+    );
+
+    Ok(())
+  }
+}
+
+/// Returns the SHA-256 hash of the contents of `path`, or `None` if it doesn't exist.
+/// Symlinks are hashed by their target.
+pub fn hash<P: AsRef<Path>>(path: P) -> Result<Option<String>> {
+  let path = path.as_ref();
+  if !util::exists(path) {
+    return Ok(None);
+  }
+
+  if path.is_symlink() {
+    let target = fs::read_link(path)?;
+    let contents = format!("symlink {}", target.to_string_lossy());
+    return Ok(Some(format!("{:x}", Sha256::digest(contents.as_bytes()))));
+  }
+
+  Ok(Some(format!("{:x}", Sha256::digest(&local::read(path)?))))
+}
+
+/// Returns the key of `remote_file` in `State::files`.
+fn key<P: AsRef<Path>>(remote_file: P) -> String {
+  let remote_file = remote_file.as_ref();
+  remote_file
+    .strip_prefix(config::tittle_config_dir())
+    .unwrap_or(remote_file)
+    .to_string_lossy()
+    .to_string()
+}
+
+/// Returns the path of the `.state.json` file.
+pub fn tittle_state_file() -> PathBuf {
+  config::tittle_config_dir().join(".state.json")
+}
+
+/// Returns the State struct representing `.state.json`, or an empty state if this
+/// machine has never synced.
+pub fn get_state() -> Result<State> {
+  let state_file = tittle_state_file();
+  if !state_file.exists() {
+    return Ok(State::default());
+  }
+
+  Ok(serde_json::from_str(&fs::read_to_string(state_file)?)?)
+}
+
+/// Saves the state `state` to `.state.json`. Does nothing in dry-run mode.
+pub fn write_state(state: &State) -> Result<()> {
+  if util::dry_run() {
+    return Ok(());
+  }
+
+  writeln!(
+    fs::File::create(tittle_state_file())?,
+    "{}",
+    serde_json::to_string_pretty(&state)?
+  )?;
+
+  Ok(())
+}
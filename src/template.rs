@@ -0,0 +1,828 @@
+use crate::config::Var;
+use crate::err;
+use crate::util::color;
+
+use anyhow::Result;
+use regex::Regex;
+use std::collections::HashMap;
+
+/// A parsed template.
+///
+/// Templates substitute variables written as `{{var}}`, and support the blocks
+/// `{{#if cond}}...{{else}}...{{/if}}` and `{{#each list}}...{{/each}}`. A condition is
+/// either a single operand, which is true if it is defined and `Var::is_truthy`, or a
+/// comparison of two operands with `==` or `!=`. Operands are variables, or literals
+/// such as `"DP-1"`, `2` or `true`. Inside `{{#each}}`, the current item is `this` and
+/// its position is `@index`, starting at 0, while `@first` and `@last` are whether it
+/// is the first or last item.
+///
+/// Variables and operands can be followed by filters, as in `{{font | upper}}` or
+/// `{{size | default: "10"}}`, which are applied from left to right. See `Filter`.
+///
+/// A block tag alone on its line, such as `{{/if}}`, removes its whole line from the
+/// output, so that blocks don't leave empty lines behind.
+pub struct Template {
+  nodes: Vec<Node>,
+}
+
+/// A part of a template. Nodes that can fail to render keep their line, for errors.
+///
+/// * `Text` - Text that is output as is.
+/// * `Var` - A variable to substitute.
+/// * `If` - A conditional block, with the nodes output when its condition is true or
+///          false.
+/// * `Each` - A loop over a list variable, with the nodes output for each item.
+enum Node {
+  Text(String),
+  Var(Expr, usize),
+  If(Cond, Vec<Node>, Vec<Node>),
+  Each(String, Vec<Node>, usize),
+}
+
+/// A condition of an `{{#if}}` block.
+///
+/// * `Truthy` - Whether an expression is defined and true.
+/// * `Eq` - Whether two expressions are equal, or not equal if the `bool` is false.
+enum Cond {
+  Truthy(Expr),
+  Eq(Expr, Expr, bool),
+}
+
+/// An operand followed by the filters applied to it, as in `{{font | upper}}`.
+struct Expr {
+  operand: Operand,
+  filters: Vec<Filter>,
+}
+
+/// An operand of a condition, which is either a variable or a literal value.
+enum Operand {
+  Var(String),
+  Literal(Var),
+}
+
+/// A filter of an expression, written after a `|`, with its arguments after a `:`.
+///
+/// * `Default` - `default: "value"`, the value to use if the variable is undefined.
+/// * `Upper` - `upper`, converts to uppercase.
+/// * `Lower` - `lower`, converts to lowercase.
+/// * `Trim` - `trim`, removes leading and trailing whitespace.
+/// * `Replace` - `replace: "from", "to"`, replaces every occurrence of `from` with `to`.
+/// * `Quote` - `quote: "shell"`, quotes the value as a string of `Lang`.
+/// * `Escape` - `escape: "json"`, escapes the value to go inside a string of `Lang`,
+///              without adding the quotes.
+enum Filter {
+  Default(Var),
+  Upper,
+  Lower,
+  Trim,
+  Replace(String, String),
+  Quote(Lang),
+  Escape(Lang),
+}
+
+/// A language whose strings a value can be quoted or escaped for. Shell strings are
+/// single quoted, and JSON and TOML strings are double quoted.
+#[derive(Clone, Copy)]
+enum Lang {
+  Shell,
+  Json,
+  Toml,
+}
+
+/// A piece of a template source, before blocks are matched up.
+///
+/// * `Text` - Text outside of tags.
+/// * `Var` - A `{{var}}` tag.
+/// * `If` - An `{{#if cond}}` tag.
+/// * `Else` - An `{{else}}` tag.
+/// * `Each` - An `{{#each list}}` tag.
+/// * `End` - An `{{/if}}` or `{{/each}}` tag, with the name of the block it closes.
+enum Token {
+  Text(String),
+  Var(Expr),
+  If(Cond),
+  Else,
+  Each(String),
+  End(&'static str),
+}
+
+/// The current item of an enclosing `{{#each}}` loop, along with its index and the
+/// length of its list.
+type Frame = (Var, usize, usize);
+
+impl Template {
+  /// Parses the template `source`.
+  pub fn parse(source: &str) -> Result<Template> {
+    let mut tokens = tokenize(source)?.into_iter();
+    let (nodes, _) = parse_block(&mut tokens, None)?;
+
+    Ok(Template { nodes })
+  }
+
+  /// Returns the names of the variables that the template uses.
+  pub fn vars(&self) -> Vec<&str> {
+    let mut vars = Vec::new();
+    nodes_vars(&self.nodes, &mut vars);
+    vars
+  }
+
+  /// Renders the template, substituting the variables in `vars`.
+  pub fn render(&self, vars: &HashMap<String, Var>) -> Result<String> {
+    let mut out = String::new();
+    render_nodes(&self.nodes, vars, &mut Vec::new(), &mut out)?;
+
+    Ok(out)
+  }
+}
+
+/// Splits `source` into tokens, along with the line each one starts on.
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>> {
+  let re = Regex::new(r"\{\{(.*?)\}\}").unwrap();
+  let line_of = |pos: usize| source[..pos].matches('\n').count() + 1;
+
+  let mut tokens = Vec::new();
+  let mut pos = 0;
+  for captures in re.captures_iter(source) {
+    let tag = captures.get(0).unwrap();
+    let line = line_of(tag.start());
+    let token = parse_tag(captures[1].trim(), line)?;
+
+    // a block tag alone on its line takes the whole line with it
+    let line_start = source[..tag.start()].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[tag.end()..]
+      .find('\n')
+      .map_or(source.len(), |i| tag.end() + i + 1);
+    let standalone = !matches!(token, Token::Var(_))
+      && line_start >= pos
+      && source[line_start..tag.start()].trim().is_empty()
+      && source[tag.end()..line_end].trim().is_empty();
+
+    let (text_end, next) = if standalone {
+      (line_start, line_end)
+    } else {
+      (tag.start(), tag.end())
+    };
+
+    if text_end > pos {
+      tokens.push((Token::Text(source[pos..text_end].to_owned()), line_of(pos)));
+    }
+    tokens.push((token, line));
+    pos = next;
+  }
+
+  if pos < source.len() {
+    tokens.push((Token::Text(source[pos..].to_owned()), line_of(pos)));
+  }
+
+  Ok(tokens)
+}
+
+/// Parses the contents of a tag on `line`, without its braces.
+fn parse_tag(tag: &str, line: usize) -> Result<Token> {
+  let (keyword, rest) = match tag.split_once(char::is_whitespace) {
+    Some((keyword, rest)) => (keyword, rest.trim()),
+    None => (tag, ""),
+  };
+
+  match (keyword, rest) {
+    ("#if", cond) => Ok(Token::If(parse_cond(cond, line)?)),
+    ("#each", list) if is_name(list) => Ok(Token::Each(list.to_owned())),
+    ("#each", list) => line_err(line, format!("invalid list {}", color::emphasis(list))),
+    ("else", "") => Ok(Token::Else),
+    ("/if", "") => Ok(Token::End("if")),
+    ("/each", "") => Ok(Token::End("each")),
+    _ => Ok(Token::Var(parse_expr(&lex(tag, line)?, line)?)),
+  }
+}
+
+/// Splits an expression or condition on `line` into its words, which are quoted
+/// strings, `|`, `:`, `,`, `==`, `!=`, and anything else up to whitespace.
+fn lex(source: &str, line: usize) -> Result<Vec<&str>> {
+  let re = Regex::new(r#"^\s*("(?:[^"\\]|\\.)*"|==|!=|[|:,]|[^\s|:,"=!]+)"#).unwrap();
+
+  let mut words = Vec::new();
+  let mut rest = source;
+  while !rest.trim().is_empty() {
+    match re.captures(rest) {
+      Some(captures) => {
+        words.push(captures.get(1).unwrap().as_str());
+        rest = &rest[captures.get(0).unwrap().end()..];
+      }
+      None => {
+        return line_err(line, format!("unexpected {}", color::emphasis(rest.trim())))
+      }
+    }
+  }
+
+  Ok(words)
+}
+
+/// Parses the `words` of an expression on `line`, an operand followed by filters.
+fn parse_expr(words: &[&str], line: usize) -> Result<Expr> {
+  let mut parts = words.split(|word| *word == "|");
+
+  let operand = match parts.next() {
+    Some([operand]) => parse_operand(operand, line)?,
+    _ => {
+      return line_err(
+        line,
+        format!("invalid expression {}", color::emphasis(words.join(" "))),
+      )
+    }
+  };
+
+  let filters = parts
+    .map(|filter| parse_filter(filter, line))
+    .collect::<Result<_>>()?;
+
+  Ok(Expr { operand, filters })
+}
+
+/// Parses the `words` of a filter on `line`, its name followed by its arguments.
+fn parse_filter(words: &[&str], line: usize) -> Result<Filter> {
+  let (name, args) = match words {
+    [name] => (*name, Vec::new()),
+    [name, ":", args @ ..] => {
+      let mut parsed = Vec::new();
+      for (i, arg) in args.iter().enumerate() {
+        match (i % 2, *arg) {
+          (1, ",") => (),
+          (0, arg) => match parse_operand(arg, line)? {
+            Operand::Literal(literal) => parsed.push(literal),
+            Operand::Var(_) => {
+              return line_err(
+                line,
+                format!(
+                  "filter argument {} must be a string, number or boolean",
+                  color::emphasis(arg)
+                ),
+              )
+            }
+          },
+          _ => return line_err(line, format!("unexpected {}", color::emphasis(*arg))),
+        }
+      }
+      (*name, parsed)
+    }
+    _ => {
+      return line_err(
+        line,
+        format!("invalid filter {}", color::emphasis(words.join(" "))),
+      )
+    }
+  };
+
+  let lang = |arg: &Var| match arg.to_string().as_str() {
+    "shell" => Ok(Lang::Shell),
+    "json" => Ok(Lang::Json),
+    "toml" => Ok(Lang::Toml),
+    other => line_err(
+      line,
+      format!(
+        "can't quote for {}, expected shell, json or toml",
+        color::emphasis(other)
+      ),
+    ),
+  };
+
+  match (name, args.as_slice()) {
+    ("default", [value]) => Ok(Filter::Default(value.clone())),
+    ("upper", []) => Ok(Filter::Upper),
+    ("lower", []) => Ok(Filter::Lower),
+    ("trim", []) => Ok(Filter::Trim),
+    ("replace", [from, to]) => Ok(Filter::Replace(from.to_string(), to.to_string())),
+    ("quote", [arg]) => Ok(Filter::Quote(lang(arg)?)),
+    ("escape", [arg]) => Ok(Filter::Escape(lang(arg)?)),
+    ("default" | "upper" | "lower" | "trim" | "replace" | "quote" | "escape", _) => {
+      line_err(
+        line,
+        format!(
+          "wrong number of arguments for filter {}",
+          color::emphasis(name)
+        ),
+      )
+    }
+    _ => line_err(line, format!("unknown filter {}", color::emphasis(name))),
+  }
+}
+
+/// Parses the condition of an `{{#if}}` block on `line`.
+fn parse_cond(cond: &str, line: usize) -> Result<Cond> {
+  let words = lex(cond, line)?;
+
+  match words.iter().position(|word| *word == "==" || *word == "!=") {
+    Some(i) => Ok(Cond::Eq(
+      parse_expr(&words[..i], line)?,
+      parse_expr(&words[i + 1..], line)?,
+      words[i] == "==",
+    )),
+    None => Ok(Cond::Truthy(parse_expr(&words, line)?)),
+  }
+}
+
+/// Parses an operand on `line`. Literals are JSON strings, numbers and booleans.
+fn parse_operand(operand: &str, line: usize) -> Result<Operand> {
+  if let Ok(literal) = serde_json::from_str::<Var>(operand) {
+    if matches!(literal, Var::String(_) | Var::Number(_) | Var::Bool(_)) {
+      return Ok(Operand::Literal(literal));
+    }
+  }
+
+  if is_name(operand) {
+    Ok(Operand::Var(operand.to_owned()))
+  } else {
+    line_err(
+      line,
+      format!("invalid operand {}", color::emphasis(operand)),
+    )
+  }
+}
+
+/// Parses `tokens` into nodes until the end of the current block, which is `block`
+/// along with the line it was opened on, or the whole template if `block` is `None`.
+/// Returns the nodes, along with whether the block was ended by an `{{else}}`.
+fn parse_block<I: Iterator<Item = (Token, usize)>>(
+  tokens: &mut I,
+  block: Option<(&'static str, usize)>,
+) -> Result<(Vec<Node>, bool)> {
+  let mut nodes = Vec::new();
+
+  while let Some((token, line)) = tokens.next() {
+    match token {
+      Token::Text(text) => nodes.push(Node::Text(text)),
+      Token::Var(var) => nodes.push(Node::Var(var, line)),
+      Token::If(cond) => {
+        let (then, has_else) = parse_block(tokens, Some(("if", line)))?;
+        let otherwise = if has_else {
+          parse_block(tokens, Some(("else", line)))?.0
+        } else {
+          Vec::new()
+        };
+
+        nodes.push(Node::If(cond, then, otherwise));
+      }
+      Token::Each(list) => {
+        let (body, _) = parse_block(tokens, Some(("each", line)))?;
+        nodes.push(Node::Each(list, body, line));
+      }
+      Token::Else => match block {
+        Some(("if", _)) => return Ok((nodes, true)),
+        _ => {
+          return line_err(line, format!("unexpected {}", color::emphasis("{{else}}")))
+        }
+      },
+      Token::End(end) => match block {
+        Some(("else", _)) if end == "if" => return Ok((nodes, false)),
+        Some((name, _)) if name == end => return Ok((nodes, false)),
+        _ => {
+          return line_err(
+            line,
+            format!(
+              "unexpected {}",
+              color::emphasis(format!("{{{{/{}}}}}", end))
+            ),
+          )
+        }
+      },
+    }
+  }
+
+  match block {
+    Some((name, line)) => {
+      let name = if name == "else" { "if" } else { name };
+      line_err(
+        line,
+        format!(
+          "{} is never closed",
+          color::emphasis(format!("{{{{#{}}}}}", name))
+        ),
+      )
+    }
+    None => Ok((nodes, false)),
+  }
+}
+
+/// Adds the names of the variables used by `nodes` to `vars`.
+fn nodes_vars<'a>(nodes: &'a [Node], vars: &mut Vec<&'a str>) {
+  for node in nodes {
+    let exprs = match node {
+      Node::Text(_) => vec![],
+      Node::Var(expr, _) => vec![expr],
+      Node::If(Cond::Truthy(expr), ..) => vec![expr],
+      Node::If(Cond::Eq(left, right, _), ..) => vec![left, right],
+      Node::Each(list, ..) => {
+        vars.push(list);
+        vec![]
+      }
+    };
+
+    for expr in exprs {
+      if let Operand::Var(var) = &expr.operand {
+        vars.push(var);
+      }
+    }
+
+    match node {
+      Node::If(_, then, otherwise) => {
+        nodes_vars(then, vars);
+        nodes_vars(otherwise, vars);
+      }
+      Node::Each(_, body, _) => nodes_vars(body, vars),
+      _ => (),
+    }
+  }
+}
+
+/// Renders `nodes` into `out`, where `frames` are the enclosing `{{#each}}` loops.
+fn render_nodes(
+  nodes: &[Node],
+  vars: &HashMap<String, Var>,
+  frames: &mut Vec<Frame>,
+  out: &mut String,
+) -> Result<()> {
+  for node in nodes {
+    match node {
+      Node::Text(text) => out.push_str(text),
+      Node::Var(expr, line) => match value(expr, vars, frames) {
+        Some(value) => out.push_str(&value.to_string()),
+        None => match &expr.operand {
+          Operand::Var(var) => return no_value(var, *line),
+          Operand::Literal(_) => unreachable!("literals always have a value"),
+        },
+      },
+      Node::If(cond, then, otherwise) => {
+        let nodes = if eval(cond, vars, frames) {
+          then
+        } else {
+          otherwise
+        };
+        render_nodes(nodes, vars, frames, out)?;
+      }
+      Node::Each(list, body, line) => {
+        let items = match lookup(list, vars, frames) {
+          Some(Var::List(items)) => items,
+          Some(_) => {
+            return line_err(
+              *line,
+              format!("variable {} is not a list", color::emphasis(list)),
+            )
+          }
+          None => return no_value(list, *line),
+        };
+
+        for (index, item) in items.iter().enumerate() {
+          frames.push((item.clone(), index, items.len()));
+          render_nodes(body, vars, frames, out)?;
+          frames.pop();
+        }
+      }
+    }
+  }
+
+  Ok(())
+}
+
+/// Returns the value of the variable `var`, where `this`, `@index`, `@first` and
+/// `@last` refer to the innermost `{{#each}}` loop in `frames`.
+fn lookup(var: &str, vars: &HashMap<String, Var>, frames: &[Frame]) -> Option<Var> {
+  match (var, frames.last()) {
+    ("this", Some((item, _, _))) => Some(item.clone()),
+    ("@index", Some((_, index, _))) => Some(Var::Number((*index).into())),
+    ("@first", Some((_, index, _))) => Some(Var::Bool(*index == 0)),
+    ("@last", Some((_, index, len))) => Some(Var::Bool(index + 1 == *len)),
+    _ => vars.get(var).cloned(),
+  }
+}
+
+/// Returns the value of `expr`, or `None` if it is an undefined variable without a
+/// `default` filter.
+fn value(expr: &Expr, vars: &HashMap<String, Var>, frames: &[Frame]) -> Option<Var> {
+  let mut value = match &expr.operand {
+    Operand::Var(var) => lookup(var, vars, frames),
+    Operand::Literal(literal) => Some(literal.clone()),
+  };
+
+  for filter in &expr.filters {
+    value = match (filter, value) {
+      (Filter::Default(default), None) => Some(default.clone()),
+      (filter, Some(value)) => Some(apply(filter, value)),
+      (_, None) => None,
+    };
+  }
+
+  value
+}
+
+/// Returns `value` after applying `filter` to it. Every filter but `default` turns its
+/// value into a string.
+fn apply(filter: &Filter, value: Var) -> Var {
+  let string = value.to_string();
+  Var::String(match filter {
+    Filter::Default(_) => return value,
+    Filter::Upper => string.to_uppercase(),
+    Filter::Lower => string.to_lowercase(),
+    Filter::Trim => string.trim().to_owned(),
+    Filter::Replace(from, to) => string.replace(from, to),
+    Filter::Quote(Lang::Shell) => format!("'{}'", escape(&string, Lang::Shell)),
+    Filter::Quote(lang) => format!("\"{}\"", escape(&string, *lang)),
+    Filter::Escape(lang) => escape(&string, *lang),
+  })
+}
+
+/// Escapes `string` to go inside a string of `lang`.
+fn escape(string: &str, lang: Lang) -> String {
+  match lang {
+    Lang::Shell => string.replace('\'', r"'\''"),
+    Lang::Json => {
+      let quoted = serde_json::to_string(string).unwrap();
+      quoted[1..quoted.len() - 1].to_owned()
+    }
+    Lang::Toml => string
+      .chars()
+      .map(|c| match c {
+        '\\' => r"\\".to_owned(),
+        '"' => r#"\""#.to_owned(),
+        '\n' => r"\n".to_owned(),
+        '\t' => r"\t".to_owned(),
+        '\r' => r"\r".to_owned(),
+        '\u{8}' => r"\b".to_owned(),
+        '\u{c}' => r"\f".to_owned(),
+        c if c.is_control() => format!("\\u{:04X}", c as u32),
+        c => c.to_string(),
+      })
+      .collect(),
+  }
+}
+
+/// Returns whether `cond` holds. An undefined variable is false, and isn't equal to
+/// anything.
+fn eval(cond: &Cond, vars: &HashMap<String, Var>, frames: &[Frame]) -> bool {
+  match cond {
+    Cond::Truthy(expr) => {
+      value(expr, vars, frames).is_some_and(|value| value.is_truthy())
+    }
+    Cond::Eq(left, right, equal) => {
+      match (value(left, vars, frames), value(right, vars, frames)) {
+        (Some(left), Some(right)) => (left.to_string() == right.to_string()) == *equal,
+        _ => !*equal,
+      }
+    }
+  }
+}
+
+/// Returns whether `name` is a valid variable name.
+fn is_name(name: &str) -> bool {
+  Regex::new(r"^@?[A-Za-z0-9_.]+$").unwrap().is_match(name)
+}
+
+/// Returns the error for the variable `var` on `line` having no value.
+fn no_value<O>(var: &str, line: usize) -> Result<O> {
+  line_err(
+    line,
+    format!(
+      "this machine has no value for variable {}",
+      color::emphasis(var)
+    ),
+  )
+}
+
+/// Returns an error with `msg`, about `line` of a template.
+fn line_err<S: Into<String>, O>(line: usize, msg: S) -> Result<O> {
+  err::err(format!("line {}: {}", line, msg.into()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Returns the variables in the JSON object `json`.
+  fn vars(json: &str) -> HashMap<String, Var> {
+    serde_json::from_str(json).unwrap()
+  }
+
+  /// Renders `source` with the variables in the JSON object `json`.
+  fn render(source: &str, json: &str) -> String {
+    Template::parse(source)
+      .unwrap()
+      .render(&vars(json))
+      .unwrap()
+  }
+
+  /// Returns the error of parsing and rendering `source` with the variables in the
+  /// JSON object `json`, without colors.
+  fn error(source: &str, json: &str) -> String {
+    colored::control::set_override(false);
+    match Template::parse(source).and_then(|template| template.render(&vars(json))) {
+      Ok(out) => panic!("expected an error, rendered {:?}", out),
+      Err(e) => e.to_string(),
+    }
+  }
+
+  #[test]
+  fn substitutes_variables() {
+    assert_eq!(
+      render("hello {{ name }}!", r#"{"name": "world"}"#),
+      "hello world!"
+    );
+    assert_eq!(
+      render("{{n}} {{b}}", r#"{"n": 1.5, "b": true}"#),
+      "1.5 true"
+    );
+    assert_eq!(render("{{xs}}", r#"{"xs": ["a", 2]}"#), "a, 2");
+    assert_eq!(render("no tags\n", "{}"), "no tags\n");
+  }
+
+  #[test]
+  fn trims_standalone_block_lines() {
+    let source = "a\n  {{#if x}}\nb\n{{else}}  \nc\n{{/if}}\nd\n";
+    assert_eq!(render(source, r#"{"x": true}"#), "a\nb\nd\n");
+    assert_eq!(render(source, r#"{"x": false}"#), "a\nc\nd\n");
+
+    let source = "{{#each xs}}\n- {{this}}\n{{/each}}";
+    assert_eq!(render(source, r#"{"xs": [1, 2]}"#), "- 1\n- 2\n");
+  }
+
+  #[test]
+  fn keeps_lines_of_inline_tags() {
+    assert_eq!(
+      render("a {{#if x}}b{{/if}} c\n", r#"{"x": true}"#),
+      "a b c\n"
+    );
+    assert_eq!(render("a {{#if x}}b{{/if}}\n", r#"{"x": false}"#), "a \n");
+    assert_eq!(render("{{x}}\n", r#"{"x": ""}"#), "\n");
+  }
+
+  #[test]
+  fn evaluates_conditions() {
+    let source = "{{#if x}}yes{{else}}no{{/if}}";
+    assert_eq!(render(source, r#"{"x": "a"}"#), "yes");
+    assert_eq!(render(source, r#"{"x": ""}"#), "no");
+    assert_eq!(render(source, r#"{"x": false}"#), "no");
+    assert_eq!(render(source, r#"{"x": []}"#), "no");
+    assert_eq!(render(source, "{}"), "no");
+
+    let source = r#"{{#if os == "linux"}}L{{/if}}{{#if os != "linux"}}O{{/if}}"#;
+    assert_eq!(render(source, r#"{"os": "linux"}"#), "L");
+    assert_eq!(render(source, r#"{"os": "macos"}"#), "O");
+    assert_eq!(render(source, "{}"), "O");
+
+    assert_eq!(render("{{#if n == 2}}two{{/if}}", r#"{"n": 2}"#), "two");
+    assert_eq!(render("{{#if n == 2}}two{{/if}}", r#"{"n": "2"}"#), "two");
+    assert_eq!(render("{{#if b == true}}on{{/if}}", r#"{"b": true}"#), "on");
+  }
+
+  #[test]
+  fn nests_blocks() {
+    let source = "{{#each xss}}{{#each this}}{{this}}{{/each}};{{/each}}";
+    assert_eq!(render(source, r#"{"xss": [["a", "b"], ["c"]]}"#), "ab;c;");
+
+    let source = "{{#if x}}{{#if y}}xy{{else}}x{{/if}}{{else}}{{#if y}}y{{/if}}{{/if}}";
+    assert_eq!(render(source, r#"{"x": true, "y": true}"#), "xy");
+    assert_eq!(render(source, r#"{"x": true, "y": false}"#), "x");
+    assert_eq!(render(source, r#"{"x": false, "y": true}"#), "y");
+    assert_eq!(render(source, r#"{"x": false, "y": false}"#), "");
+  }
+
+  #[test]
+  fn loops_with_positions() {
+    let source = "{{#each xs}}{{@index}}={{this}} {{/each}}";
+    assert_eq!(render(source, r#"{"xs": ["a", "b"]}"#), "0=a 1=b ");
+
+    let source = "[{{#each xs}}{{#if @first}}<{{/if}}{{this}}{{#if @last}}>{{else}}, {{/if}}{{/each}}]";
+    assert_eq!(render(source, r#"{"xs": [1, 2, 3]}"#), "[<1, 2, 3>]");
+    assert_eq!(render(source, r#"{"xs": [1]}"#), "[<1>]");
+    assert_eq!(render(source, r#"{"xs": []}"#), "[]");
+
+    let source = "{{#each xss}}{{#each this}}{{@index}}{{/each}}{{@index}} {{/each}}";
+    assert_eq!(render(source, r#"{"xss": [[1, 2], [3]]}"#), "010 01 ");
+  }
+
+  #[test]
+  fn applies_filters() {
+    assert_eq!(render("{{x | upper}}", r#"{"x": "aB"}"#), "AB");
+    assert_eq!(render("{{x | lower}}", r#"{"x": "aB"}"#), "ab");
+    assert_eq!(render("{{x|trim|upper}}", r#"{"x": "  ab \n"}"#), "AB");
+    assert_eq!(
+      render(r#"{{x | replace: "/", " | "}}"#, r#"{"x": "a/b"}"#),
+      "a | b"
+    );
+    assert_eq!(render(r#"{{"lit" | upper}}"#, "{}"), "LIT");
+  }
+
+  #[test]
+  fn applies_defaults() {
+    assert_eq!(render(r#"{{x | default: "10"}}"#, "{}"), "10");
+    assert_eq!(render("{{x | default: 10}}", r#"{"x": 12}"#), "12");
+    assert_eq!(render(r#"{{x | upper | default: "a"}}"#, "{}"), "a");
+    assert_eq!(render(r#"{{x | default: "a" | upper}}"#, "{}"), "A");
+    assert_eq!(render(r#"{{#if x | default: true}}on{{/if}}"#, "{}"), "on");
+  }
+
+  #[test]
+  fn quotes_and_escapes() {
+    let vars = r#"{"x": "it's \"a\"\\\n\tb\u0001"}"#;
+    assert_eq!(
+      render(r#"{{x | quote: "shell"}}"#, vars),
+      "'it'\\''s \"a\"\\\n\tb\u{1}'"
+    );
+    assert_eq!(
+      render(r#"{{x | escape: "shell"}}"#, vars),
+      "it'\\''s \"a\"\\\n\tb\u{1}"
+    );
+    assert_eq!(
+      render(r#"{{x | quote: "json"}}"#, vars),
+      r#""it's \"a\"\\\n\tb\u0001""#
+    );
+    assert_eq!(
+      render(r#"{{x | escape: "json"}}"#, vars),
+      r#"it's \"a\"\\\n\tb\u0001"#
+    );
+    assert_eq!(
+      render(r#"{{x | quote: "toml"}}"#, vars),
+      r#""it's \"a\"\\\n\tb\u0001""#
+    );
+    assert_eq!(
+      render(r#"{{x | escape: "toml"}}"#, r#"{"x": "\r\b\f\u007f"}"#),
+      r"\r\b\f\u007F"
+    );
+  }
+
+  #[test]
+  fn reports_filter_errors() {
+    assert_eq!(
+      error(r#"{{x | replace: "a"}}"#, "{}"),
+      "line 1: wrong number of arguments for filter replace"
+    );
+    assert_eq!(
+      error("{{x | upper: 1}}", "{}"),
+      "line 1: wrong number of arguments for filter upper"
+    );
+    assert_eq!(error("{{x | frob}}", "{}"), "line 1: unknown filter frob");
+    assert_eq!(
+      error("{{x | default: y}}", "{}"),
+      "line 1: filter argument y must be a string, number or boolean"
+    );
+    assert_eq!(
+      error(r#"{{x | replace: "a" "b"}}"#, "{}"),
+      r#"line 1: unexpected "b""#
+    );
+    assert_eq!(
+      error(r#"{{x | quote: "perl"}}"#, "{}"),
+      "line 1: can't quote for perl, expected shell, json or toml"
+    );
+    assert_eq!(error("{{x | }}", "{}"), "line 1: invalid filter ");
+    assert_eq!(error("{{x = 1}}", "{}"), "line 1: unexpected = 1");
+  }
+
+  #[test]
+  fn reports_errors_with_lines() {
+    assert_eq!(
+      error("a\n{{#if x}}\nb\n", "{}"),
+      "line 2: {{#if}} is never closed"
+    );
+    assert_eq!(
+      error("{{#if x}}\n{{else}}\n", "{}"),
+      "line 1: {{#if}} is never closed"
+    );
+    assert_eq!(
+      error("\n\n{{#each xs}}", "{}"),
+      "line 3: {{#each}} is never closed"
+    );
+    assert_eq!(
+      error("{{#if x}}\n{{/each}}", "{}"),
+      "line 2: unexpected {{/each}}"
+    );
+    assert_eq!(error("a\n\n{{else}}", "{}"), "line 3: unexpected {{else}}");
+    assert_eq!(
+      error("{{#each xs}}{{else}}{{/each}}", "{}"),
+      "line 1: unexpected {{else}}"
+    );
+    assert_eq!(
+      error("{{#each a b}}{{/each}}", "{}"),
+      "line 1: invalid list a b"
+    );
+    assert_eq!(error("{{a b}}", "{}"), "line 1: invalid expression a b");
+    assert_eq!(error("{{a-b}}", "{}"), "line 1: invalid operand a-b");
+    assert_eq!(
+      error("a\n{{x}}", "{}"),
+      "line 2: this machine has no value for variable x"
+    );
+    assert_eq!(
+      error("\n{{#each x}}{{/each}}", r#"{"x": 1}"#),
+      "line 2: variable x is not a list"
+    );
+    assert_eq!(
+      error("{{#each xs}}\n{{y}}\n{{/each}}", r#"{"xs": [1]}"#),
+      "line 2: this machine has no value for variable y"
+    );
+  }
+
+  #[test]
+  fn lists_used_variables() {
+    let template = Template::parse(
+      r#"{{a}}{{#if b == c}}{{#each d}}{{e | default: "f"}}{{/each}}{{/if}}"#,
+    )
+    .unwrap();
+    assert_eq!(template.vars(), vec!["a", "b", "c", "d", "e"]);
+  }
+}
@@ -3,6 +3,9 @@ use colored::*;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
 
 pub mod color {
   use colored::*;
@@ -19,9 +22,29 @@ pub mod color {
   }
 }
 
-/// Log the message `msg` as info.
+/// Sets whether tittle is in dry-run mode. In dry-run mode, operations are only
+/// logged, and nothing is written to the remote or local filesystem.
+pub fn set_dry_run(dry_run: bool) {
+  DRY_RUN.store(dry_run, Ordering::Relaxed);
+}
+
+/// Returns whether tittle is in dry-run mode.
+pub fn dry_run() -> bool {
+  DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Log the message `msg` as info. In dry-run mode, these are the planned operations.
 pub fn info<S: std::fmt::Display>(msg: S) {
-  println!("{} {}", "INFO:".green(), msg);
+  if dry_run() {
+    println!("{} {}", "DRY RUN:".cyan(), msg);
+  } else {
+    println!("{} {}", "INFO:".green(), msg);
+  }
+}
+
+/// Log the message `msg` as a warning.
+pub fn warn<S: std::fmt::Display>(msg: S) {
+  println!("{} {}", "WARN:".yellow(), msg);
 }
 
 /// Log the message `msg` as error.
@@ -29,12 +52,195 @@ pub fn error<S: std::fmt::Display>(msg: S) {
   println!("{} {}", "ERROR:".red(), msg);
 }
 
-/// Recursively copy the contents of one directory to another.
-/// https://stackoverflow.com/a/60406693/6101419
-pub fn copy_dir<U: AsRef<Path>, V: AsRef<Path>>(
+/// Prints `msg` and returns the trimmed line that the user enters in response.
+pub fn prompt<S: std::fmt::Display>(msg: S) -> Result<String> {
+  use std::io::prelude::*;
+
+  print!("{} {}", "INPUT:".purple(), msg);
+  std::io::stdout().flush()?;
+
+  let mut line = String::new();
+  std::io::stdin().read_line(&mut line)?;
+
+  Ok(line.trim().to_string())
+}
+
+/// Creates the directory `path` and any missing parents, readable only by this user.
+pub fn create_private_dir<P: AsRef<Path>>(path: P) -> Result<()> {
+  use std::os::unix::fs::DirBuilderExt;
+
+  Ok(
+    fs::DirBuilder::new()
+      .recursive(true)
+      .mode(0o700)
+      .create(path)?,
+  )
+}
+
+/// Writes `contents` to the file `path`, creating it with the permission bits `mode`.
+pub fn write_with_mode<P: AsRef<Path>, C: AsRef<[u8]>>(
+  path: P,
+  contents: C,
+  mode: u32,
+) -> Result<()> {
+  use std::io::Write;
+  use std::os::unix::fs::OpenOptionsExt;
+
+  fs::OpenOptions::new()
+    .write(true)
+    .create(true)
+    .truncate(true)
+    .mode(mode)
+    .open(path)?
+    .write_all(contents.as_ref())?;
+
+  Ok(())
+}
+
+/// Creates the parent directory of `path` if it doesn't exist.
+pub fn create_parent<P: AsRef<Path>>(path: P) -> Result<()> {
+  let path = path.as_ref();
+  let parent = path
+    .parent()
+    .unwrap_or_else(|| panic!("Path has no parent {:?}", path));
+
+  if !parent.is_dir() {
+    fs::create_dir_all(parent)?;
+  }
+
+  Ok(())
+}
+
+/// Copies the file `from` to `to`, along with its permissions and modification time.
+pub fn copy_file<U: AsRef<Path>, V: AsRef<Path>>(
   from: U,
   to: V,
 ) -> Result<(), std::io::Error> {
+  fs::copy(&from, &to)?;
+  let modified = fs::metadata(&from)?.modified()?;
+  fs::File::options()
+    .write(true)
+    .open(to)?
+    .set_modified(modified)
+}
+
+/// Removes the directories containing `path` that are empty, up to but excluding
+/// `root`.
+pub fn remove_empty_parents<P: AsRef<Path>, Q: AsRef<Path>>(
+  path: P,
+  root: Q,
+) -> Result<()> {
+  let root = root.as_ref();
+  let mut parent = path.as_ref().parent();
+  while let Some(dir) = parent {
+    if dir == root || !dir.starts_with(root) || fs::read_dir(dir)?.next().is_some() {
+      break;
+    }
+
+    fs::remove_dir(dir)?;
+    parent = dir.parent();
+  }
+
+  Ok(())
+}
+
+/// Removes the empty directories under `root`, including `root` itself if it ends up
+/// empty. Directories that still contain files are left alone.
+pub fn remove_empty_dirs<P: AsRef<Path>>(root: P) -> Result<()> {
+  for dir in walkdir::WalkDir::new(root).contents_first(true) {
+    let dir = dir?;
+    if dir.file_type().is_dir() && fs::read_dir(dir.path())?.next().is_none() {
+      fs::remove_dir(dir.path())?;
+    }
+  }
+
+  Ok(())
+}
+
+/// Removes `path`, whether it is a file, a symlink, or a directory. Does nothing if
+/// it doesn't exist.
+pub fn remove_all<P: AsRef<Path>>(path: P) -> Result<()> {
+  let path = path.as_ref();
+  if !exists(path) {
+    return Ok(());
+  }
+
+  if path.is_dir() && !path.is_symlink() {
+    fs::remove_dir_all(path)?;
+  } else {
+    fs::remove_file(path)?;
+  }
+
+  Ok(())
+}
+
+/// Returns whether `path` exists, without following it if it is a symlink.
+pub fn exists<P: AsRef<Path>>(path: P) -> bool {
+  fs::symlink_metadata(path).is_ok()
+}
+
+/// Returns the target of the symlink `link`. If the target is an absolute path under
+/// `root`, it is made relative to the directory containing `link`, so that it still
+/// points within `root` once `root` is copied elsewhere.
+pub fn symlink_target<P: AsRef<Path>, Q: AsRef<Path>>(
+  link: P,
+  root: Q,
+) -> Result<PathBuf> {
+  let link = link.as_ref();
+  let target = fs::read_link(link)?;
+  if !target.is_absolute() || !target.starts_with(root) {
+    return Ok(target);
+  }
+
+  let parent = link.parent().unwrap();
+  let common = parent
+    .components()
+    .zip(target.components())
+    .take_while(|(a, b)| a == b)
+    .count();
+
+  let mut relative = PathBuf::new();
+  for _ in parent.components().skip(common) {
+    relative.push("..");
+  }
+  for component in target.components().skip(common) {
+    relative.push(component);
+  }
+
+  Ok(relative)
+}
+
+/// Creates a symlink at `to` with the same target as the symlink `from`, replacing
+/// anything at `to`. Targets under `root` are made relative, see `symlink_target`.
+pub fn copy_symlink<P: AsRef<Path>, Q: AsRef<Path>, R: AsRef<Path>>(
+  from: P,
+  to: Q,
+  root: R,
+) -> Result<()> {
+  let to = to.as_ref();
+  let target = symlink_target(from, root)?;
+
+  match fs::symlink_metadata(to) {
+    Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(to)?,
+    Ok(_) => fs::remove_file(to)?,
+    Err(_) => create_parent(to)?,
+  }
+
+  std::os::unix::fs::symlink(target, to)?;
+
+  Ok(())
+}
+
+/// Recursively copy the contents of one directory to another, keeping permissions and
+/// modification times. Symlinks are copied as symlinks, and are never followed. Paths
+/// for which `ignored` returns true, given their path relative to `from`, are skipped.
+/// Files are copied with `local::fetch`, so `from` can be the path of a system entry.
+/// https://stackoverflow.com/a/60406693/6101419
+pub fn copy_dir<U: AsRef<Path>, V: AsRef<Path>, F: Fn(&Path) -> bool>(
+  from: U,
+  to: V,
+  ignored: F,
+) -> Result<()> {
   let mut stack = Vec::new();
   stack.push(PathBuf::from(from.as_ref()));
 
@@ -58,15 +264,19 @@ pub fn copy_dir<U: AsRef<Path>, V: AsRef<Path>>(
     for entry in fs::read_dir(working_path)? {
       let entry = entry?;
       let path = entry.path();
-      if path.is_dir() {
+      if ignored(path.strip_prefix(from.as_ref()).unwrap_or(&path)) {
+        continue;
+      }
+
+      let file_type = entry.file_type()?;
+      if file_type.is_dir() {
         stack.push(path);
-      } else {
-        match path.file_name() {
-          Some(filename) => {
-            let dest_path = dest.join(filename);
-            fs::copy(&path, &dest_path)?;
-          }
-          None => (),
+      } else if let Some(filename) = path.file_name() {
+        let dest_path = dest.join(filename);
+        if file_type.is_symlink() {
+          copy_symlink(&path, &dest_path, from.as_ref())?;
+        } else {
+          crate::local::fetch(&path, &dest_path)?;
         }
       }
     }
@@ -77,11 +287,40 @@ pub fn copy_dir<U: AsRef<Path>, V: AsRef<Path>>(
 
 /// Returns the output of the `diff` command on the two files. This uses `colordiff`
 /// if it is available. If there is no diff between the two files, the returns `None`.
+/// If only one of the files exists, then the diff just says so. If either file is a
+/// symlink, then their targets are compared instead of their contents.
 pub fn diff<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<Option<String>> {
   let (from, to) = (from.as_ref(), to.as_ref());
 
-  if !to.exists() {
-    return Ok(Some(format!("New file {}", color::path(to))));
+  let (from_link, to_link) = (fs::read_link(from).ok(), fs::read_link(to).ok());
+  if from_link.is_some() || to_link.is_some() {
+    if from_link == to_link {
+      return Ok(None);
+    }
+
+    let describe = |path: &Path, link: Option<PathBuf>| match link {
+      Some(target) => format!(
+        "{} is a symlink to {}",
+        color::path(path),
+        color::path(target)
+      ),
+      None if path.is_dir() => format!("{} is a directory", color::path(path)),
+      None if path.exists() => format!("{} is a file", color::path(path)),
+      None => format!("{} doesn't exist", color::path(path)),
+    };
+
+    return Ok(Some(format!(
+      "{}\n{}",
+      describe(from, from_link),
+      describe(to, to_link)
+    )));
+  }
+
+  match (from.exists(), to.exists()) {
+    (false, false) => return Ok(None),
+    (true, false) => return Ok(Some(format!("Only in {}", color::path(from)))),
+    (false, true) => return Ok(Some(format!("Only in {}", color::path(to)))),
+    (true, true) => (),
   }
 
   let diff_bin = match which::which("colordiff") {
@@ -89,11 +328,30 @@ pub fn diff<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<Option<Str
     Err(_) => "diff",
   };
 
-  let output = Command::new(diff_bin)
-    .arg("-ru")
-    .arg(from)
-    .arg(to)
-    .output()?;
+  // local files of system entries that this user can't read are piped in instead
+  let output = if to.is_file() && fs::File::open(to).is_err() {
+    let mut child = Command::new(diff_bin)
+      .arg("-u")
+      .arg("--label")
+      .arg(from)
+      .arg("--label")
+      .arg(to)
+      .arg(from)
+      .arg("-")
+      .stdin(std::process::Stdio::piped())
+      .stdout(std::process::Stdio::piped())
+      .spawn()?;
+
+    let contents = crate::local::read(to)?;
+    std::io::Write::write_all(&mut child.stdin.take().unwrap(), &contents)?;
+    child.wait_with_output()?
+  } else {
+    Command::new(diff_bin)
+      .arg("-ru")
+      .arg(from)
+      .arg(to)
+      .output()?
+  };
 
   let output = match output.status.code() {
     None => None,
@@ -109,22 +367,191 @@ pub fn diff<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<Option<Str
   Ok(output)
 }
 
-/// Returns a this machine's unique identifier.
+/// Returns a this machine's unique identifier, `user@hostname`.
 pub fn machine_id() -> Result<String> {
-  // use std::io::prelude::*;
-  // let mut file = fs::File::open("/etc/machine-id")?;
-  // let mut contents = String::new();
-  // file.read_to_string(&mut contents)?;
-  // Ok(contents.trim().to_string())
-
-  use std::str::from_utf8;
-
-  let hostname = Command::new("hostname").output()?.stdout;
-  let whoami = Command::new("whoami").output()?.stdout;
-
-  Ok(format!(
-    "{}@{}",
-    from_utf8(&whoami)?.trim(),
-    from_utf8(&hostname)?.trim()
-  ))
+  Ok(format!("{}@{}", username()?, hostname()?))
+}
+
+/// Returns the hostname of this machine.
+pub fn hostname() -> Result<String> {
+  let mut buf = vec![0u8; 256];
+  if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+    return Err(std::io::Error::last_os_error().into());
+  }
+
+  let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+  Ok(String::from_utf8(buf[..len].to_vec())?)
+}
+
+/// Returns the name of the user running tittle.
+pub fn username() -> Result<String> {
+  match user_name(unsafe { libc::getuid() }) {
+    Some(name) => Ok(name),
+    None => crate::err::err("Can't find the name of the current user"),
+  }
+}
+
+/// Returns the name of the user with the id `uid`, or `None` if there is no such user.
+pub fn user_name(uid: libc::uid_t) -> Option<String> {
+  id_name(
+    |passwd, buf, result| unsafe {
+      libc::getpwuid_r(uid, passwd, buf.as_mut_ptr(), buf.len(), result)
+    },
+    |passwd: &libc::passwd| passwd.pw_name,
+  )
+}
+
+/// Returns the name of the group with the id `gid`, or `None` if there is no such
+/// group.
+pub fn group_name(gid: libc::gid_t) -> Option<String> {
+  id_name(
+    |group, buf, result| unsafe {
+      libc::getgrgid_r(gid, group, buf.as_mut_ptr(), buf.len(), result)
+    },
+    |group: &libc::group| group.gr_name,
+  )
+}
+
+/// Looks up an entry of the user or group database with `lookup`, a reentrant libc
+/// function such as `getpwuid_r`, and returns the name `name` points to in it. The
+/// buffer is grown until the entry fits.
+fn id_name<T, L, N>(lookup: L, name: N) -> Option<String>
+where
+  L: Fn(&mut T, &mut [libc::c_char], &mut *mut T) -> libc::c_int,
+  N: Fn(&T) -> *const libc::c_char,
+{
+  let mut entry: T = unsafe { std::mem::zeroed() };
+  let mut buf = vec![0 as libc::c_char; 1024];
+
+  loop {
+    let mut result = std::ptr::null_mut();
+    match lookup(&mut entry, &mut buf, &mut result) {
+      libc::ERANGE => buf.resize(buf.len() * 2, 0),
+      0 if !result.is_null() => {
+        let name = unsafe { std::ffi::CStr::from_ptr(name(&entry)) };
+        return Some(name.to_string_lossy().into_owned());
+      }
+      _ => return None,
+    }
+  }
+}
+
+/// Expands `pattern` into the paths it refers to. Braces such as `{i3,polybar}` are
+/// expanded first, then a leading `~` is replaced with the home directory, and finally
+/// globs such as `*.fish` are matched against the filesystem. Patterns without globs
+/// are returned as is, even if they don't exist.
+pub fn expand(pattern: &str) -> Result<Vec<PathBuf>> {
+  let mut paths = Vec::new();
+
+  for pattern in expand_braces(pattern) {
+    let pattern = match pattern.strip_prefix('~') {
+      Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+        format!("{}{}", std::env::var("HOME")?, rest)
+      }
+      _ => pattern,
+    };
+
+    if !pattern.contains(['*', '?', '[']) {
+      paths.push(PathBuf::from(pattern));
+      continue;
+    }
+
+    let mut matches = match glob::glob(&pattern) {
+      Ok(matches) => matches.collect::<Result<Vec<_>, _>>()?,
+      Err(_) => {
+        return crate::err::err(format!("Invalid pattern {}", color::emphasis(pattern)))
+      }
+    };
+
+    if matches.is_empty() {
+      return crate::err::err(format!("No paths match {}", color::emphasis(pattern)));
+    }
+
+    matches.sort();
+    paths.extend(matches);
+  }
+
+  Ok(paths)
+}
+
+/// Expands the first group of braces in `pattern` that contains a top-level comma,
+/// like a shell does, and recursively expands the results. Groups without a comma,
+/// such as `{}`, are left as is.
+fn expand_braces(pattern: &str) -> Vec<String> {
+  let mut open = None;
+  let mut depth = 0;
+  let mut commas = Vec::new();
+
+  for (i, c) in pattern.char_indices() {
+    match c {
+      '{' => {
+        if depth == 0 {
+          open = Some(i);
+          commas.clear();
+        }
+        depth += 1;
+      }
+      ',' if depth == 1 => commas.push(i),
+      '}' if depth > 0 => {
+        depth -= 1;
+        if depth > 0 || commas.is_empty() {
+          continue;
+        }
+
+        let open = open.unwrap();
+        let (prefix, suffix) = (&pattern[..open], &pattern[i + 1..]);
+
+        let mut bounds = vec![open];
+        bounds.extend(&commas);
+        bounds.push(i);
+
+        return bounds
+          .windows(2)
+          .flat_map(|bound| {
+            expand_braces(&format!(
+              "{}{}{}",
+              prefix,
+              &pattern[bound[0] + 1..bound[1]],
+              suffix
+            ))
+          })
+          .collect();
+      }
+      _ => (),
+    }
+  }
+
+  vec![pattern.to_owned()]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn expands_braces() {
+    assert_eq!(expand_braces("~/.{vimrc,zshrc}"), ["~/.vimrc", "~/.zshrc"]);
+    assert_eq!(expand_braces("{a,b}{1,2}"), ["a1", "a2", "b1", "b2"]);
+    assert_eq!(expand_braces("rc{,.bak}"), ["rc", "rc.bak"]);
+    assert_eq!(expand_braces("{a,{b,c}d}e"), ["ae", "bde", "cde"]);
+    assert_eq!(expand_braces("{a,b{}}"), ["a", "b{}"]);
+    assert_eq!(expand_braces("{a}{b,c}"), ["{a}b", "{a}c"]);
+  }
+
+  #[test]
+  fn looks_up_id_names() {
+    assert_eq!(user_name(0).as_deref(), Some("root"));
+    assert_eq!(group_name(0).as_deref(), Some("root"));
+    assert_eq!(user_name(3_999_999_999), None);
+    assert_eq!(group_name(3_999_999_999), None);
+  }
+
+  #[test]
+  fn leaves_braces_without_commas() {
+    assert_eq!(expand_braces("plain"), ["plain"]);
+    assert_eq!(expand_braces("a{}b"), ["a{}b"]);
+    assert_eq!(expand_braces("{a}"), ["{a}"]);
+    assert_eq!(expand_braces("{a,b"), ["{a,b"]);
+    assert_eq!(expand_braces("a,b}"), ["a,b}"]);
+  }
 }
@@ -0,0 +1,95 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+/// A temporary home directory, which is deleted when dropped. Tittle creates its
+/// repository there the first time it runs.
+struct Home {
+  dir: PathBuf,
+}
+
+impl Home {
+  fn new(name: &str) -> Home {
+    let dir = std::env::temp_dir().join(format!("tittle-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    Home { dir }
+  }
+
+  /// Returns the path `path` inside the home directory.
+  fn path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+    self.dir.join(path)
+  }
+
+  /// Runs tittle with `args` in the home directory, and asserts that it succeeds.
+  fn tittle(&self, args: &[&str]) {
+    let output = Command::new(env!("CARGO_BIN_EXE_tittle"))
+      .args(args)
+      .current_dir(&self.dir)
+      .env("HOME", &self.dir)
+      .env("GIT_AUTHOR_NAME", "tittle")
+      .env("GIT_AUTHOR_EMAIL", "tittle@localhost")
+      .env("GIT_COMMITTER_NAME", "tittle")
+      .env("GIT_COMMITTER_EMAIL", "tittle@localhost")
+      .output()
+      .unwrap();
+
+    assert!(
+      output.status.success(),
+      "tittle {} failed:\n{}{}",
+      args.join(" "),
+      String::from_utf8_lossy(&output.stdout),
+      String::from_utf8_lossy(&output.stderr)
+    );
+  }
+}
+
+impl Drop for Home {
+  fn drop(&mut self) {
+    let _ = fs::remove_dir_all(&self.dir);
+  }
+}
+
+#[test]
+fn undo_restores_the_symlink_of_a_removed_entry() {
+  let home = Home::new("undo-remove-symlink");
+  let (cfg, remote) = (home.path("cfg"), home.path(".tittle/cfg"));
+  fs::create_dir(&cfg).unwrap();
+  fs::write(cfg.join("x"), "x").unwrap();
+
+  home.tittle(&["track", cfg.to_str().unwrap(), "-n", "cfg", "-m", "symlink"]);
+  assert_eq!(fs::read_link(&cfg).unwrap(), remote);
+
+  home.tittle(&["remove", "cfg"]);
+  assert!(!cfg.is_symlink());
+  assert_eq!(fs::read_to_string(cfg.join("x")).unwrap(), "x");
+
+  home.tittle(&["undo"]);
+  assert_eq!(fs::read_link(&cfg).unwrap(), remote);
+
+  home.tittle(&["sync"]);
+  assert_eq!(fs::read_to_string(remote.join("x")).unwrap(), "x");
+}
+
+#[test]
+fn commits_machine_local_names_inside_entries() {
+  let home = Home::new("machine-local-names");
+  let app = home.path("app");
+  fs::create_dir(&app).unwrap();
+  fs::write(app.join(".state.json"), "{}").unwrap();
+  fs::write(app.join(".secrets.json"), "{}").unwrap();
+
+  home.tittle(&["track", app.to_str().unwrap(), "-n", "app"]);
+
+  let output = Command::new("git")
+    .arg("-C")
+    .arg(home.path(".tittle"))
+    .args(["ls-files", "app"])
+    .output()
+    .unwrap();
+  assert_eq!(
+    String::from_utf8(output.stdout).unwrap(),
+    "app/.secrets.json\napp/.state.json\n"
+  );
+}